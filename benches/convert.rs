@@ -0,0 +1,42 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{ImageBuffer, RgbImage};
+
+#[path = "../src/convert.rs"]
+mod convert;
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+
+// Conversione usata in precedenza: un ImageBuffer nuovo a ogni frame, riempito pixel per pixel
+fn bgra_to_rgb_image(frame: &[u8], width: usize, height: usize) -> RgbImage {
+    let mut img_buffer: RgbImage = ImageBuffer::new(width as u32, height as u32);
+    for (i, pixel) in img_buffer.pixels_mut().enumerate() {
+        let idx = i * 4;
+        *pixel = image::Rgb([frame[idx + 2], frame[idx + 1], frame[idx]]);
+    }
+    img_buffer
+}
+
+fn bench_conversion(c: &mut Criterion) {
+    let frame: Vec<u8> = (0..WIDTH * HEIGHT * 4).map(|i| (i * 7) as u8).collect();
+
+    let mut group = c.benchmark_group("bgra_to_rgb_1080p");
+    group.bench_function("image_buffer", |b| {
+        b.iter(|| bgra_to_rgb_image(black_box(&frame), WIDTH, HEIGHT))
+    });
+    let mut rgb = Vec::new();
+    group.bench_function("convert_reused_buffer", |b| {
+        b.iter(|| convert::bgra_to_rgb(black_box(&frame), WIDTH * 4, WIDTH, HEIGHT, &mut rgb))
+    });
+    group.finish();
+
+    // Stessa area ritagliata da un frame con stride più ampio della riga
+    let stride = (WIDTH + 64) * 4;
+    let padded: Vec<u8> = (0..stride * HEIGHT).map(|i| (i * 7) as u8).collect();
+    c.bench_function("bgra_to_rgb_1080p_stride", |b| {
+        b.iter(|| convert::bgra_to_rgb(black_box(&padded), stride, WIDTH, HEIGHT, &mut rgb))
+    });
+}
+
+criterion_group!(benches, bench_conversion);
+criterion_main!(benches);
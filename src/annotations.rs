@@ -0,0 +1,895 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+use eframe::egui::{pos2, vec2, Align2, Color32, FontDefinitions, FontId, Pos2, Rect, Vec2};
+use eframe::egui::epaint::text::Fonts;
+use crate::exclusion::ScreenRect;
+use crate::protocol;
+
+// Annotazioni del presentatore. Sono salvate in pixel fisici dello schermo virtuale, come le
+// finestre escluse, e il caster le disegna in ogni frame prima della compressione: restano
+// allineate all'area condivisa con qualsiasi scala del display e arrivano ai receiver anche
+// quando la barra degli strumenti è chiusa. Spessori e dimensioni del testo sono in pixel.
+// In alternativa il caster le invia ai receiver come messaggi vettoriali (AnnotationChannel),
+// con coordinate normalizzate rispetto all'area condivisa.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Annotation {
+    Rectangle {
+        rect: Rect,
+        color: Color32,
+        width: f32,
+    },
+    Arrow {
+        start: Pos2,
+        end: Pos2,
+        color: Color32,
+        width: f32,
+    },
+    Text {
+        pos: Pos2,
+        content: String,
+        color: Color32,
+        size: f32,
+    },
+    Pen {
+        points: Vec<Pos2>,
+        color: Color32,
+        width: f32,
+    },
+    // Il colore è salvato pieno: la trasparenza viene applicata quando si disegna
+    Highlighter {
+        points: Vec<Pos2>,
+        color: Color32,
+        width: f32,
+    },
+    // Ellisse inscritta nel rettangolo
+    Ellipse {
+        rect: Rect,
+        color: Color32,
+        width: f32,
+    },
+    Line {
+        start: Pos2,
+        end: Pos2,
+        color: Color32,
+        width: f32,
+    },
+    // Cerchio pieno con il numero del passo, per seguire una procedura punto per punto
+    Marker {
+        center: Pos2,
+        number: u32,
+        color: Color32,
+        radius: f32,
+    },
+}
+
+// Raggio dei marcatori rispetto alla dimensione del testo, e del numero rispetto al raggio
+pub const MARKER_RADIUS_FACTOR: f32 = 0.9;
+const MARKER_FONT_FACTOR: f32 = 1.2;
+
+pub fn marker_font(radius: f32) -> FontId {
+    FontId::proportional(radius * MARKER_FONT_FACTOR)
+}
+
+// Numero in nero sui colori chiari e in bianco su quelli scuri
+pub fn marker_text_color(color: Color32) -> Color32 {
+    let luminance = 0.299 * color.r() as f32 + 0.587 * color.g() as f32 + 0.114 * color.b() as f32;
+    if luminance > 150.0 { Color32::BLACK } else { Color32::WHITE }
+}
+
+// I passi ripartono da 1 dopo aver cancellato le annotazioni.
+pub fn next_marker_number(annotations: &[Annotation]) -> u32 {
+    annotations.iter()
+        .filter_map(|a| match a {
+            Annotation::Marker { number, .. } => Some(*number),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0) + 1
+}
+
+// Con Maiusc premuto la linea viene agganciata a multipli di 15 gradi.
+pub fn snap_angle(start: Pos2, end: Pos2) -> Pos2 {
+    let delta = end - start;
+    let step = 15f32.to_radians();
+    start + Vec2::angled((delta.angle() / step).round() * step) * delta.length()
+}
+
+// Contorno dell'ellisse come spezzata chiusa, con lati di qualche pixel.
+pub fn ellipse_points(rect: Rect) -> Vec<Pos2> {
+    let radius = rect.size() / 2.0;
+    let segments = ((radius.x + radius.y) * std::f32::consts::PI / 4.0).clamp(16.0, 256.0) as usize;
+    (0..=segments)
+        .map(|i| {
+            let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+            rect.center() + vec2(radius.x * angle.cos(), radius.y * angle.sin())
+        })
+        .collect()
+}
+
+// L'evidenziatore è più largo della penna e semitrasparente, per lasciar leggere il testo sotto
+pub const HIGHLIGHTER_WIDTH_FACTOR: f32 = 6.0;
+const HIGHLIGHTER_ALPHA: u8 = 90;
+
+pub fn highlighter_color(color: Color32) -> Color32 {
+    let [r, g, b, _] = color.to_srgba_unmultiplied();
+    Color32::from_rgba_unmultiplied(r, g, b, HIGHLIGHTER_ALPHA)
+}
+
+// Arrotonda un tratto a mano libera con due passate dell'algoritmo di Chaikin, mantenendo gli estremi.
+pub fn smooth_stroke(points: &[Pos2]) -> Vec<Pos2> {
+    let mut points = points.to_vec();
+    for _ in 0..2 {
+        if points.len() < 3 {
+            break;
+        }
+        let mut smoothed = vec![points[0]];
+        for pair in points.windows(2) {
+            smoothed.push(pair[0].lerp(pair[1], 0.25));
+            smoothed.push(pair[0].lerp(pair[1], 0.75));
+        }
+        smoothed.push(points[points.len() - 1]);
+        points = smoothed;
+    }
+    points
+}
+
+// Colore, spessore e dimensione del testo per le nuove annotazioni, in punti dell'interfaccia.
+// Vengono ricordati tra una sessione e l'altra.
+const STYLE_FILE: &str = "annotation_style.txt";
+
+pub const PALETTE: [(Color32, &str); 7] = [
+    (Color32::WHITE, "Bianco"),
+    (Color32::BLACK, "Nero"),
+    (Color32::from_rgb(230, 40, 40), "Rosso"),
+    (Color32::from_rgb(255, 200, 0), "Giallo"),
+    (Color32::from_rgb(40, 180, 70), "Verde"),
+    (Color32::from_rgb(30, 120, 255), "Blu"),
+    (Color32::from_rgb(220, 60, 220), "Magenta"),
+];
+
+pub const STROKE_RANGE: std::ops::RangeInclusive<f32> = 1.0..=12.0;
+pub const FONT_SIZE_RANGE: std::ops::RangeInclusive<f32> = 10.0..=48.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnnotationStyle {
+    pub color: Color32,
+    pub stroke: f32,
+    pub font_size: f32,
+}
+
+impl Default for AnnotationStyle {
+    fn default() -> Self {
+        Self { color: Color32::WHITE, stroke: 2.0, font_size: 14.0 }
+    }
+}
+
+// Il file contiene una riga: "r g b a spessore dimensione_testo".
+pub fn load_style() -> AnnotationStyle {
+    fs::read_to_string(STYLE_FILE).ok()
+        .and_then(|content| parse_style(content.trim()))
+        .unwrap_or_default()
+}
+
+pub fn save_style(style: &AnnotationStyle) -> io::Result<()> {
+    let [r, g, b, a] = style.color.to_srgba_unmultiplied();
+    fs::write(STYLE_FILE, format!("{} {} {} {} {} {}\n", r, g, b, a, style.stroke, style.font_size))
+}
+
+fn parse_style(line: &str) -> Option<AnnotationStyle> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| fields[i].parse::<u8>().ok();
+    Some(AnnotationStyle {
+        color: Color32::from_rgba_unmultiplied(channel(0)?, channel(1)?, channel(2)?, channel(3)?),
+        stroke: fields[4].parse::<f32>().ok()?.clamp(*STROKE_RANGE.start(), *STROKE_RANGE.end()),
+        font_size: fields[5].parse::<f32>().ok()?.clamp(*FONT_SIZE_RANGE.start(), *FONT_SIZE_RANGE.end()),
+    })
+}
+
+impl Annotation {
+    fn map(&self, point: impl Fn(Pos2) -> Pos2, scale: f32) -> Annotation {
+        match self {
+            Annotation::Rectangle { rect, color, width } => Annotation::Rectangle {
+                rect: Rect::from_two_pos(point(rect.min), point(rect.max)),
+                color: *color,
+                width: width * scale,
+            },
+            Annotation::Arrow { start, end, color, width } => Annotation::Arrow {
+                start: point(*start),
+                end: point(*end),
+                color: *color,
+                width: width * scale,
+            },
+            Annotation::Text { pos, content, color, size } => Annotation::Text {
+                pos: point(*pos),
+                content: content.clone(),
+                color: *color,
+                size: size * scale,
+            },
+            Annotation::Pen { points, color, width } => Annotation::Pen {
+                points: points.iter().map(|p| point(*p)).collect(),
+                color: *color,
+                width: width * scale,
+            },
+            Annotation::Highlighter { points, color, width } => Annotation::Highlighter {
+                points: points.iter().map(|p| point(*p)).collect(),
+                color: *color,
+                width: width * scale,
+            },
+            Annotation::Ellipse { rect, color, width } => Annotation::Ellipse {
+                rect: Rect::from_two_pos(point(rect.min), point(rect.max)),
+                color: *color,
+                width: width * scale,
+            },
+            Annotation::Line { start, end, color, width } => Annotation::Line {
+                start: point(*start),
+                end: point(*end),
+                color: *color,
+                width: width * scale,
+            },
+            Annotation::Marker { center, number, color, radius } => Annotation::Marker {
+                center: point(*center),
+                number: *number,
+                color: *color,
+                radius: radius * scale,
+            },
+        }
+    }
+
+    // Da pixel dello schermo a frazioni dell'area condivisa; spessori e testo rispetto all'altezza.
+    pub fn normalized(&self, origin: (i32, i32), size: (usize, usize)) -> Annotation {
+        let (width, height) = (size.0.max(1) as f32, size.1.max(1) as f32);
+        self.map(|p| pos2((p.x - origin.0 as f32) / width, (p.y - origin.1 as f32) / height), 1.0 / height)
+    }
+
+    // Da coordinate normalizzate al rettangolo in cui il receiver mostra (o registra) il frame.
+    pub fn denormalized(&self, target: Rect) -> Annotation {
+        self.map(|p| target.min + vec2(p.x * target.width(), p.y * target.height()), target.height())
+    }
+
+    // Zone dello schermo in cui la finestra trasparente mostra l'annotazione al presentatore. Con
+    // le annotazioni vettoriali vanno tolte dal video, altrimenti i receiver non potrebbero nasconderle.
+    pub fn footprint(&self, rects: &mut Vec<ScreenRect>) {
+        match self {
+            Annotation::Rectangle { rect, width, .. } => {
+                let corners = [rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom()];
+                for i in 0..4 {
+                    segment_footprint(corners[i], corners[(i + 1) % 4], *width, rects);
+                }
+            }
+            Annotation::Arrow { start, end, width, .. } => {
+                if let Some((base, head)) = arrow_geometry(*start, *end, *width) {
+                    segment_footprint(*start, base, *width, rects);
+                    rects.push(screen_rect(head[0].min(head[1]).min(head[2]), head[0].max(head[1]).max(head[2]), 2.0));
+                }
+            }
+            Annotation::Text { pos, content, size, .. } => {
+                // Stima per eccesso: i glifi proporzionali sono più stretti della dimensione del font
+                let extent = vec2(content.chars().count() as f32 * size * 0.7, size * 1.4);
+                rects.push(screen_rect(*pos, *pos + extent, 2.0));
+            }
+            Annotation::Pen { points, width, .. } | Annotation::Highlighter { points, width, .. } => {
+                for pair in points.windows(2) {
+                    segment_footprint(pair[0], pair[1], *width, rects);
+                }
+                if let [point] = points.as_slice() {
+                    segment_footprint(*point, *point, *width, rects);
+                }
+            }
+            Annotation::Ellipse { rect, width, .. } => {
+                for pair in ellipse_points(*rect).windows(2) {
+                    segment_footprint(pair[0], pair[1], *width, rects);
+                }
+            }
+            Annotation::Line { start, end, width, .. } => segment_footprint(*start, *end, *width, rects),
+            Annotation::Marker { center, radius, .. } => {
+                rects.push(screen_rect(*center - Vec2::splat(*radius), *center + Vec2::splat(*radius), 2.0));
+            }
+        }
+    }
+}
+
+fn screen_rect(min: Pos2, max: Pos2, margin: f32) -> ScreenRect {
+    ScreenRect {
+        min_x: (min.x - margin).floor() as i32,
+        min_y: (min.y - margin).floor() as i32,
+        max_x: (max.x + margin).ceil() as i32,
+        max_y: (max.y + margin).ceil() as i32,
+    }
+}
+
+// Piccoli rettangoli lungo il segmento: uno solo che contenga una linea obliqua coprirebbe
+// gran parte dell'area intorno
+fn segment_footprint(a: Pos2, b: Pos2, width: f32, rects: &mut Vec<ScreenRect>) {
+    let margin = width / 2.0 + 2.0;
+    let steps = ((b - a).length() / (4.0 * margin)).ceil().max(1.0) as usize;
+    for i in 0..steps {
+        let p = a + (b - a) * (i as f32 / steps as f32);
+        let q = a + (b - a) * ((i + 1) as f32 / steps as f32);
+        rects.push(screen_rect(p.min(q), p.max(q), margin));
+    }
+}
+
+// Punta della freccia, in multipli dello spessore della linea
+const ARROWHEAD_LENGTH: f32 = 8.0;
+const ARROWHEAD_WIDTH: f32 = 5.0;
+
+// Linea della freccia e vertici della punta, usati sia dall'interfaccia sia dal caster.
+pub fn arrow_geometry(start: Pos2, end: Pos2, width: f32) -> Option<(Pos2, [Pos2; 3])> {
+    let dir = end - start;
+    let length = dir.length();
+    if length < 5.0 {
+        return None;
+    }
+    let dir = dir / length;
+    let base = end - dir * ARROWHEAD_LENGTH * width;
+    let perp = vec2(-dir.y, dir.x) * (ARROWHEAD_WIDTH * width / 2.0);
+    Some((base, [end, base + perp, base - perp]))
+}
+
+pub struct AnnotationRenderer {
+    // Creato solo alla prima annotazione di testo: caricare i font è costoso
+    fonts: Option<Fonts>,
+    // Copertura dei pixel di un tratto, riusata tra un frame e l'altro
+    coverage: Vec<f32>,
+}
+
+impl AnnotationRenderer {
+    pub fn new() -> Self {
+        Self { fonts: None, coverage: Vec::new() }
+    }
+
+    // origin è la posizione del frame (BGRA compatto) nello schermo virtuale.
+    pub fn draw(&mut self, bgra: &mut [u8], width: usize, height: usize, origin: (i32, i32), annotations: &[Annotation]) {
+        let origin = pos2(origin.0 as f32, origin.1 as f32);
+        let canvas = Canvas { pixels: bgra, rgb: [2, 1, 0], width, height, origin, coverage: &mut self.coverage };
+        draw_annotations(&mut self.fonts, canvas, annotations);
+    }
+
+    // Per le registrazioni del receiver, che lavorano su immagini RGBA in coordinate del frame.
+    pub fn draw_rgba(&mut self, rgba: &mut [u8], width: usize, height: usize, annotations: &[Annotation]) {
+        let canvas = Canvas { pixels: rgba, rgb: [0, 1, 2], width, height, origin: Pos2::ZERO, coverage: &mut self.coverage };
+        draw_annotations(&mut self.fonts, canvas, annotations);
+    }
+}
+
+fn draw_annotations(fonts: &mut Option<Fonts>, mut canvas: Canvas, annotations: &[Annotation]) {
+    // Una passata per frame: i testi impaginati restano in cache finché vengono disegnati
+    if let Some(fonts) = fonts {
+        fonts.begin_pass(1.0, 2048);
+    }
+    for annotation in annotations {
+        match annotation {
+            Annotation::Rectangle { rect, color, width } => {
+                let corners = [rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom(), rect.left_top()];
+                canvas.polyline(&corners, *width, *color);
+            }
+            Annotation::Arrow { start, end, color, width } => {
+                if let Some((base, head)) = arrow_geometry(*start, *end, *width) {
+                    canvas.polyline(&[*start, base], *width, *color);
+                    canvas.triangle(head, *color);
+                }
+            }
+            Annotation::Pen { points, color, width } => canvas.polyline(points, *width, *color),
+            Annotation::Highlighter { points, color, width } => canvas.polyline(points, *width, highlighter_color(*color)),
+            Annotation::Text { pos, content, color, size } => {
+                let fonts = fonts.get_or_insert_with(|| Fonts::new(1.0, 2048, FontDefinitions::default()));
+                canvas.text(fonts, *pos, Align2::LEFT_TOP, content, FontId::proportional(*size), *color);
+            }
+            Annotation::Ellipse { rect, color, width } => canvas.polyline(&ellipse_points(*rect), *width, *color),
+            Annotation::Line { start, end, color, width } => canvas.polyline(&[*start, *end], *width, *color),
+            Annotation::Marker { center, number, color, radius } => {
+                canvas.disc(*center, *radius, *color);
+                let fonts = fonts.get_or_insert_with(|| Fonts::new(1.0, 2048, FontDefinitions::default()));
+                canvas.text(fonts, *center, Align2::CENTER_CENTER, &number.to_string(), marker_font(*radius), marker_text_color(*color));
+            }
+        }
+    }
+}
+
+struct Canvas<'a> {
+    pixels: &'a mut [u8],
+    // Posizione di rosso, verde e blu nel pixel
+    rgb: [usize; 3],
+    width: usize,
+    height: usize,
+    origin: Pos2,
+    coverage: &'a mut Vec<f32>,
+}
+
+impl Canvas<'_> {
+    // Pixel del frame coperti dal rettangolo (in coordinate dello schermo), già ritagliati
+    fn pixel_range(&self, min: Pos2, max: Pos2) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let clamp_x = |v: f32| (v - self.origin.x).clamp(0.0, self.width as f32) as usize;
+        let clamp_y = |v: f32| (v - self.origin.y).clamp(0.0, self.height as f32) as usize;
+        (clamp_x(min.x.floor())..clamp_x(max.x.ceil()), clamp_y(min.y.floor())..clamp_y(max.y.ceil()))
+    }
+
+    // Centro del pixel del frame in coordinate dello schermo
+    fn pixel_center(&self, x: usize, y: usize) -> Pos2 {
+        pos2(self.origin.x + x as f32 + 0.5, self.origin.y + y as f32 + 0.5)
+    }
+
+    // Color32 è premoltiplicato: i canali sono già scalati per l'alfa
+    fn blend(&mut self, x: usize, y: usize, color: Color32, coverage: f32) {
+        let coverage = coverage.clamp(0.0, 1.0);
+        let alpha = coverage * color.a() as f32 / 255.0;
+        if alpha <= 0.0 {
+            return;
+        }
+        let idx = (y * self.width + x) * 4;
+        for (offset, value) in self.rgb.into_iter().zip([color.r(), color.g(), color.b()]) {
+            let channel = &mut self.pixels[idx + offset];
+            *channel = (*channel as f32 * (1.0 - alpha) + value as f32 * coverage).round().min(255.0) as u8;
+        }
+    }
+
+    // Tratto con estremi e giunzioni arrotondati e bordi sfumati su un pixel. La copertura di ogni
+    // pixel è la massima tra i segmenti, così le giunzioni di un tratto semitrasparente non si scuriscono.
+    fn polyline(&mut self, points: &[Pos2], width: f32, color: Color32) {
+        let Some(&first) = points.first() else {
+            return;
+        };
+        let padding = vec2(width / 2.0 + 1.0, width / 2.0 + 1.0);
+        let (min, max) = points.iter().fold((first, first), |(min, max), p| (min.min(*p), max.max(*p)));
+        let (xs, ys) = self.pixel_range(min - padding, max + padding);
+        if xs.is_empty() || ys.is_empty() {
+            return;
+        }
+        let mut coverage = std::mem::take(self.coverage);
+        coverage.clear();
+        coverage.resize(xs.len() * ys.len(), 0.0);
+        let single = [first, first];
+        let segments = if points.len() == 1 { single.windows(2) } else { points.windows(2) };
+        for pair in segments {
+            let (a, b) = (pair[0], pair[1]);
+            let (segment_xs, segment_ys) = self.pixel_range(a.min(b) - padding, a.max(b) + padding);
+            let ab = b - a;
+            let length_sq = ab.length_sq().max(f32::EPSILON);
+            for y in segment_ys {
+                for x in segment_xs.clone() {
+                    let p = self.pixel_center(x, y);
+                    let t = ((p - a).dot(ab) / length_sq).clamp(0.0, 1.0);
+                    let distance = (p - (a + ab * t)).length();
+                    let cell = &mut coverage[(y - ys.start) * xs.len() + (x - xs.start)];
+                    *cell = cell.max(width / 2.0 + 0.5 - distance);
+                }
+            }
+        }
+        for y in ys.clone() {
+            for x in xs.clone() {
+                self.blend(x, y, color, coverage[(y - ys.start) * xs.len() + (x - xs.start)]);
+            }
+        }
+        *self.coverage = coverage;
+    }
+
+    fn disc(&mut self, center: Pos2, radius: f32, color: Color32) {
+        let (xs, ys) = self.pixel_range(center - Vec2::splat(radius + 1.0), center + Vec2::splat(radius + 1.0));
+        for y in ys {
+            for x in xs.clone() {
+                let distance = (self.pixel_center(x, y) - center).length();
+                self.blend(x, y, color, radius + 0.5 - distance);
+            }
+        }
+    }
+
+    fn triangle(&mut self, points: [Pos2; 3], color: Color32) {
+        let min = points[0].min(points[1]).min(points[2]);
+        let max = points[0].max(points[1]).max(points[2]);
+        let (xs, ys) = self.pixel_range(min, max);
+        let edge = |a: Pos2, b: Pos2, p: Pos2| (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x);
+        for y in ys {
+            for x in xs.clone() {
+                let p = self.pixel_center(x, y);
+                let signs = [edge(points[0], points[1], p), edge(points[1], points[2], p), edge(points[2], points[0], p)];
+                if signs.iter().all(|&s| s >= 0.0) || signs.iter().all(|&s| s <= 0.0) {
+                    self.blend(x, y, color, 1.0);
+                }
+            }
+        }
+    }
+
+    // Il testo viene impaginato da epaint con un pixel per punto, quindi i texel dell'atlante
+    // dei glifi corrispondono ai pixel del frame.
+    // La posizione è ancorata come in egui::Painter::text.
+    fn text(&mut self, fonts: &Fonts, anchor: Pos2, align: Align2, content: &str, font: FontId, color: Color32) {
+        let galley = fonts.layout_no_wrap(content.to_string(), font, color);
+        let pos = align.anchor_size(anchor, galley.size()).min;
+        let atlas = fonts.image();
+        for glyph in galley.rows.iter().flat_map(|row| &row.glyphs) {
+            let uv = glyph.uv_rect;
+            if uv.is_nothing() {
+                continue;
+            }
+            let left_top = pos + glyph.pos.to_vec2() + uv.offset - self.origin.to_vec2();
+            let (left, top) = (left_top.x.round() as i32, left_top.y.round() as i32);
+            for ty in uv.min[1]..uv.max[1] {
+                for tx in uv.min[0]..uv.max[0] {
+                    let x = left + (tx - uv.min[0]) as i32;
+                    let y = top + (ty - uv.min[1]) as i32;
+                    if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+                        continue;
+                    }
+                    let coverage = atlas.pixels[ty as usize * atlas.size[0] + tx as usize];
+                    self.blend(x as usize, y as usize, color, coverage);
+                }
+            }
+        }
+    }
+}
+
+// Messaggi del canale vettoriale. Add e Update sostituiscono l'annotazione con quell'id, così un
+// receiver che ha perso dei pacchetti (UDP, QUIC) si riallinea alla sincronizzazione successiva.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnotationMessage {
+    Add(u32, Annotation),
+    Update(u32, Annotation),
+    Remove(u32),
+    Clear,
+}
+
+// Ogni tanto si reinvia l'elenco completo, per i receiver arrivati dopo o che hanno perso pacchetti
+const RESYNC_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct AnnotationChannel {
+    sent: Vec<Annotation>,
+    // Ultimo elenco ricevuto dal caster e area a cui si riferiva, per non ricalcolare niente quando non cambia
+    source: Vec<Annotation>,
+    source_area: ((i32, i32), (usize, usize)),
+    last_sync: Instant,
+    // Dopo aver tolto tutte le annotazioni si sincronizza ancora una volta, nel caso il Clear sia andato perso
+    resync_empty: bool,
+}
+
+impl AnnotationChannel {
+    pub fn new() -> Self {
+        Self { sent: Vec::new(), source: Vec::new(), source_area: ((0, 0), (0, 0)), last_sync: Instant::now(), resync_empty: false }
+    }
+
+    // Pacchetto con le differenze rispetto a quanto già inviato, None se non c'è niente da inviare.
+    // L'id di un'annotazione è la sua posizione nell'elenco.
+    pub fn update(&mut self, annotations: &[Annotation], origin: (i32, i32), size: (usize, usize), new_viewer: bool) -> Option<Vec<u8>> {
+        let resync_due = self.last_sync.elapsed() >= RESYNC_INTERVAL && (!self.sent.is_empty() || self.resync_empty);
+        if !new_viewer && !resync_due && self.source_area == (origin, size) && self.source == annotations {
+            return None;
+        }
+        self.source = annotations.to_vec();
+        self.source_area = (origin, size);
+        let current: Vec<Annotation> = annotations.iter().map(|a| a.normalized(origin, size)).collect();
+        let periodic = self.last_sync.elapsed() >= RESYNC_INTERVAL && (!current.is_empty() || self.resync_empty);
+        let messages = if new_viewer || periodic {
+            self.last_sync = Instant::now();
+            self.resync_empty = false;
+            std::iter::once(AnnotationMessage::Clear)
+                .chain(current.iter().enumerate().map(|(id, a)| AnnotationMessage::Add(id as u32, a.clone())))
+                .collect()
+        } else if current.is_empty() && !self.sent.is_empty() {
+            self.resync_empty = true;
+            vec![AnnotationMessage::Clear]
+        } else {
+            let mut messages = Vec::new();
+            for (id, annotation) in current.iter().enumerate() {
+                match self.sent.get(id) {
+                    None => messages.push(AnnotationMessage::Add(id as u32, annotation.clone())),
+                    Some(sent) if sent != annotation => messages.push(AnnotationMessage::Update(id as u32, annotation.clone())),
+                    Some(_) => {}
+                }
+            }
+            messages.extend((current.len()..self.sent.len()).map(|id| AnnotationMessage::Remove(id as u32)));
+            messages
+        };
+        self.sent = current;
+        if messages.is_empty() {
+            return None;
+        }
+        Some(protocol::annotations(&encode_messages(&messages)))
+    }
+}
+
+pub fn apply_messages(annotations: &mut BTreeMap<u32, Annotation>, messages: Vec<AnnotationMessage>) {
+    for message in messages {
+        match message {
+            AnnotationMessage::Add(id, annotation) | AnnotationMessage::Update(id, annotation) => {
+                annotations.insert(id, annotation);
+            }
+            AnnotationMessage::Remove(id) => {
+                annotations.remove(&id);
+            }
+            AnnotationMessage::Clear => annotations.clear(),
+        }
+    }
+}
+
+// Codifica binaria: un byte per il tipo di messaggio, l'id (u32), poi per Add e Update la forma,
+// il colore RGBA, lo spessore (dimensione del testo, raggio del marcatore) e le coordinate (f32),
+// precedute dal numero di punti per penna ed evidenziatore. Testo e marcatori terminano con il
+// contenuto e il numero del passo. Tutti i numeri sono big-endian.
+const MESSAGE_ADD: u8 = 0;
+const MESSAGE_UPDATE: u8 = 1;
+const MESSAGE_REMOVE: u8 = 2;
+const MESSAGE_CLEAR: u8 = 3;
+
+const SHAPE_RECTANGLE: u8 = 0;
+const SHAPE_ARROW: u8 = 1;
+const SHAPE_TEXT: u8 = 2;
+const SHAPE_PEN: u8 = 3;
+const SHAPE_HIGHLIGHTER: u8 = 4;
+const SHAPE_ELLIPSE: u8 = 5;
+const SHAPE_LINE: u8 = 6;
+const SHAPE_MARKER: u8 = 7;
+
+pub fn encode_messages(messages: &[AnnotationMessage]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for message in messages {
+        match message {
+            AnnotationMessage::Add(id, annotation) | AnnotationMessage::Update(id, annotation) => {
+                out.push(if matches!(message, AnnotationMessage::Add(..)) { MESSAGE_ADD } else { MESSAGE_UPDATE });
+                out.extend_from_slice(&id.to_be_bytes());
+                encode_annotation(annotation, &mut out);
+            }
+            AnnotationMessage::Remove(id) => {
+                out.push(MESSAGE_REMOVE);
+                out.extend_from_slice(&id.to_be_bytes());
+            }
+            AnnotationMessage::Clear => out.push(MESSAGE_CLEAR),
+        }
+    }
+    out
+}
+
+fn encode_annotation(annotation: &Annotation, out: &mut Vec<u8>) {
+    let (shape, color, width, points): (u8, Color32, f32, Vec<Pos2>) = match annotation {
+        Annotation::Rectangle { rect, color, width } => (SHAPE_RECTANGLE, *color, *width, vec![rect.min, rect.max]),
+        Annotation::Arrow { start, end, color, width } => (SHAPE_ARROW, *color, *width, vec![*start, *end]),
+        Annotation::Text { pos, color, size, .. } => (SHAPE_TEXT, *color, *size, vec![*pos]),
+        Annotation::Pen { points, color, width } => (SHAPE_PEN, *color, *width, points.clone()),
+        Annotation::Highlighter { points, color, width } => (SHAPE_HIGHLIGHTER, *color, *width, points.clone()),
+        Annotation::Ellipse { rect, color, width } => (SHAPE_ELLIPSE, *color, *width, vec![rect.min, rect.max]),
+        Annotation::Line { start, end, color, width } => (SHAPE_LINE, *color, *width, vec![*start, *end]),
+        Annotation::Marker { center, color, radius, .. } => (SHAPE_MARKER, *color, *radius, vec![*center]),
+    };
+    out.push(shape);
+    out.extend_from_slice(&color.to_srgba_unmultiplied());
+    out.extend_from_slice(&width.to_be_bytes());
+    // I tratti a mano libera hanno un numero variabile di punti
+    if matches!(annotation, Annotation::Pen { .. } | Annotation::Highlighter { .. }) {
+        out.extend_from_slice(&(points.len() as u32).to_be_bytes());
+    }
+    for point in points {
+        out.extend_from_slice(&point.x.to_be_bytes());
+        out.extend_from_slice(&point.y.to_be_bytes());
+    }
+    match annotation {
+        Annotation::Text { content, .. } => {
+            out.extend_from_slice(&(content.len() as u32).to_be_bytes());
+            out.extend_from_slice(content.as_bytes());
+        }
+        Annotation::Marker { number, .. } => out.extend_from_slice(&number.to_be_bytes()),
+        _ => {}
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn pos(&mut self) -> Option<Pos2> {
+        Some(pos2(self.f32()?, self.f32()?))
+    }
+}
+
+// None se il pacchetto è malformato: meglio ignorarlo che mostrare annotazioni a metà.
+pub fn decode_messages(payload: &[u8]) -> Option<Vec<AnnotationMessage>> {
+    let mut reader = Reader { data: payload };
+    let mut messages = Vec::new();
+    while !reader.data.is_empty() {
+        let message = match reader.u8()? {
+            MESSAGE_ADD => AnnotationMessage::Add(reader.u32()?, decode_annotation(&mut reader)?),
+            MESSAGE_UPDATE => AnnotationMessage::Update(reader.u32()?, decode_annotation(&mut reader)?),
+            MESSAGE_REMOVE => AnnotationMessage::Remove(reader.u32()?),
+            MESSAGE_CLEAR => AnnotationMessage::Clear,
+            _ => return None,
+        };
+        messages.push(message);
+    }
+    Some(messages)
+}
+
+fn decode_annotation(reader: &mut Reader) -> Option<Annotation> {
+    let shape = reader.u8()?;
+    let [r, g, b, a]: [u8; 4] = reader.bytes(4)?.try_into().ok()?;
+    let color = Color32::from_rgba_unmultiplied(r, g, b, a);
+    let width = reader.f32()?;
+    match shape {
+        SHAPE_RECTANGLE => Some(Annotation::Rectangle { rect: Rect::from_min_max(reader.pos()?, reader.pos()?), color, width }),
+        SHAPE_ARROW => Some(Annotation::Arrow { start: reader.pos()?, end: reader.pos()?, color, width }),
+        SHAPE_TEXT => {
+            let pos = reader.pos()?;
+            let len = reader.u32()? as usize;
+            let content = String::from_utf8(reader.bytes(len)?.to_vec()).ok()?;
+            Some(Annotation::Text { pos, content, color, size: width })
+        }
+        SHAPE_PEN | SHAPE_HIGHLIGHTER => {
+            let count = reader.u32()? as usize;
+            // Ogni punto occupa 8 byte: un conteggio più grande del pacchetto è un errore
+            if count > reader.data.len() / 8 {
+                return None;
+            }
+            let points = (0..count).map(|_| reader.pos()).collect::<Option<Vec<_>>>()?;
+            if shape == SHAPE_PEN {
+                Some(Annotation::Pen { points, color, width })
+            } else {
+                Some(Annotation::Highlighter { points, color, width })
+            }
+        }
+        SHAPE_ELLIPSE => Some(Annotation::Ellipse { rect: Rect::from_min_max(reader.pos()?, reader.pos()?), color, width }),
+        SHAPE_LINE => Some(Annotation::Line { start: reader.pos()?, end: reader.pos()?, color, width }),
+        SHAPE_MARKER => Some(Annotation::Marker { center: reader.pos()?, number: reader.u32()?, color, radius: width }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{parse_packet, Packet};
+
+    fn every_shape() -> Vec<Annotation> {
+        let color = Color32::from_rgb(230, 40, 40);
+        vec![
+            Annotation::Rectangle { rect: Rect::from_min_max(pos2(0.1, 0.2), pos2(0.3, 0.4)), color, width: 3.0 },
+            Annotation::Arrow { start: pos2(0.5, 0.5), end: pos2(0.9, 0.1), color, width: 2.0 },
+            Annotation::Text { pos: pos2(0.25, 0.75), content: "Però è così".to_string(), color, size: 24.0 },
+            Annotation::Pen { points: vec![pos2(0.0, 0.0), pos2(0.1, 0.1), pos2(0.2, 0.15)], color, width: 4.0 },
+            Annotation::Highlighter { points: vec![pos2(0.4, 0.4), pos2(0.6, 0.4)], color: Color32::YELLOW, width: 18.0 },
+            Annotation::Ellipse { rect: Rect::from_min_max(pos2(0.2, 0.2), pos2(0.4, 0.3)), color, width: 1.0 },
+            Annotation::Line { start: pos2(0.0, 1.0), end: pos2(1.0, 0.0), color, width: 12.0 },
+            Annotation::Marker { center: pos2(0.7, 0.3), number: 12, color, radius: 14.0 },
+        ]
+    }
+
+    fn every_message() -> Vec<AnnotationMessage> {
+        let mut messages = vec![AnnotationMessage::Clear];
+        for (id, annotation) in every_shape().into_iter().enumerate() {
+            messages.push(AnnotationMessage::Add(id as u32, annotation.clone()));
+            messages.push(AnnotationMessage::Update(id as u32 + 100, annotation));
+        }
+        messages.push(AnnotationMessage::Remove(u32::MAX));
+        messages
+    }
+
+    #[test]
+    fn every_message_survives_a_round_trip() {
+        let messages = every_message();
+        assert_eq!(decode_messages(&encode_messages(&messages)), Some(messages));
+        // Anche vuoti: un tratto senza punti e un testo senza caratteri
+        let empty = vec![
+            AnnotationMessage::Add(0, Annotation::Pen { points: Vec::new(), color: Color32::BLACK, width: 1.0 }),
+            AnnotationMessage::Add(1, Annotation::Text { pos: pos2(0.0, 0.0), content: String::new(), color: Color32::BLACK, size: 10.0 }),
+        ];
+        assert_eq!(decode_messages(&encode_messages(&empty)), Some(empty));
+        assert_eq!(decode_messages(&[]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn truncated_payloads_are_rejected() {
+        let messages = every_message();
+        // Posizioni in cui finisce un messaggio: lì il pacchetto troncato è ancora valido
+        let mut boundaries = Vec::new();
+        for end in 1..=messages.len() {
+            boundaries.push(encode_messages(&messages[..end]).len());
+        }
+        let payload = encode_messages(&messages);
+        for len in 1..payload.len() {
+            let decoded = decode_messages(&payload[..len]);
+            assert_eq!(decoded.is_some(), boundaries.contains(&len), "lunghezza {}", len);
+        }
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected() {
+        let text = encode_messages(&[AnnotationMessage::Add(0, every_shape()[2].clone())]);
+        // Lunghezza del testo dopo tipo, id, forma, colore, dimensione e posizione
+        let len_at = 1 + 4 + 1 + 4 + 4 + 8;
+        for len in [u32::MAX, 1_000_000] {
+            let mut oversized = text.clone();
+            oversized[len_at..len_at + 4].copy_from_slice(&len.to_be_bytes());
+            assert_eq!(decode_messages(&oversized), None);
+        }
+
+        let pen = encode_messages(&[AnnotationMessage::Add(0, every_shape()[3].clone())]);
+        let count_at = 1 + 4 + 1 + 4 + 4;
+        let mut oversized = pen.clone();
+        oversized[count_at..count_at + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(decode_messages(&oversized), None);
+
+        // Tipo di messaggio o forma sconosciuti
+        assert_eq!(decode_messages(&[9]), None);
+        let mut unknown_shape = pen;
+        unknown_shape[5] = 42;
+        assert_eq!(decode_messages(&unknown_shape), None);
+
+        // Pacchetto che dichiara più byte di quanti ne contiene
+        let mut packet = protocol::annotations(&text);
+        packet.truncate(packet.len() - 1);
+        assert!(matches!(parse_packet(&packet), Packet::EndOfStream));
+    }
+
+    fn received(packet: Option<Vec<u8>>) -> Option<Vec<AnnotationMessage>> {
+        match parse_packet(&packet?) {
+            Packet::Annotations(payload) => decode_messages(payload),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn channel_sends_only_differences() {
+        let shapes = every_shape();
+        let (origin, size) = ((100, 50), (1000, 500));
+        let normalized = |index: usize, annotations: &[Annotation]| annotations[index].normalized(origin, size);
+        let mut channel = AnnotationChannel::new();
+
+        // Un nuovo receiver riceve l'elenco completo
+        let first = received(channel.update(&shapes[..2], origin, size, true)).unwrap();
+        assert_eq!(first, vec![
+            AnnotationMessage::Clear,
+            AnnotationMessage::Add(0, normalized(0, &shapes)),
+            AnnotationMessage::Add(1, normalized(1, &shapes)),
+        ]);
+        assert_eq!(channel.update(&shapes[..2], origin, size, false), None);
+
+        let mut changed = shapes[..3].to_vec();
+        changed[1] = shapes[6].clone();
+        let diff = received(channel.update(&changed, origin, size, false)).unwrap();
+        assert_eq!(diff, vec![
+            AnnotationMessage::Update(1, normalized(1, &changed)),
+            AnnotationMessage::Add(2, normalized(2, &changed)),
+        ]);
+
+        let removed = received(channel.update(&changed[..1], origin, size, false)).unwrap();
+        assert_eq!(removed, vec![AnnotationMessage::Remove(1), AnnotationMessage::Remove(2)]);
+        assert_eq!(received(channel.update(&[], origin, size, false)), Some(vec![AnnotationMessage::Clear]));
+        assert_eq!(channel.update(&[], origin, size, false), None);
+
+        // Se l'area condivisa si sposta le coordinate normalizzate cambiano
+        let moved = received(channel.update(&shapes[..1], (0, 0), size, false)).unwrap();
+        assert_eq!(moved, vec![AnnotationMessage::Add(0, shapes[0].normalized((0, 0), size))]);
+    }
+
+    #[test]
+    fn channel_resyncs_periodically() {
+        let shapes = every_shape();
+        let mut channel = AnnotationChannel::new();
+        channel.update(&shapes[..1], (0, 0), (800, 600), false);
+        channel.last_sync -= RESYNC_INTERVAL;
+        let resync = received(channel.update(&shapes[..1], (0, 0), (800, 600), false)).unwrap();
+        assert_eq!(resync, vec![AnnotationMessage::Clear, AnnotationMessage::Add(0, shapes[0].normalized((0, 0), (800, 600)))]);
+    }
+}
@@ -1,20 +1,106 @@
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{self, Receiver, Sender};
 use tokio::net::TcpListener;
 use tokio::sync::broadcast;
 use std::error::Error;
-use std::sync::atomic::{AtomicBool, Ordering};
-use eframe::epaint::Rect;
-use scrap::{Capturer, Display};
-use image::{ImageBuffer, RgbImage, DynamicImage};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use eframe::epaint::{pos2, Rect};
 use tokio::io::AsyncWriteExt;
 use tokio::time::{sleep, Duration};
+use std::time::Instant;
 use device_query::{DeviceQuery, DeviceState, Keycode};
+use crate::{protocol, quic, rtsp, udp, web};
+use crate::pipeline::{EncodePipeline, RawFrame};
+use crate::scaling::OutputScale;
+use crate::cursor::CursorOverlay;
+use crate::follow::FollowWindow;
+use crate::annotations::{Annotation, AnnotationChannel, AnnotationRenderer};
+use crate::exclusion::{Background, ScreenRect};
+use crate::masks::{apply_masks, PrivacyMask};
+use crate::presets::RegionPreset;
+use crate::screen::{display_at, display_layout, display_name, source_geometry, DisplayGeometry, ScreenCapturer, ALL_DISPLAYS};
+use crate::window_share::{WindowBounds, WindowTracker};
+use crate::scheduler::{FrameScheduler, DEFAULT_FPS};
+use xxhash_rust::xxh3::xxh3_64;
 
+const LAYOUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const LAYOUT_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+
+pub struct CasterOptions {
+    // Sorgente iniziale: display (o ALL_DISPLAYS) e area da condividere, None per il display intero
+    pub display_index: usize,
+    pub selected_area: Option<Rect>,
+    pub web_addr: Option<String>,
+    pub rtsp_addr: Option<String>,
+    pub udp_enabled: bool,
+    pub udp_simulated_loss: f32,
+    pub quic_addr: Option<String>,
+    pub target_fps: Arc<AtomicU32>,
+    pub encoder_threads: usize,
+    pub output_scale: Arc<RwLock<OutputScale>>,
+    pub show_cursor: Arc<AtomicBool>,
+    pub highlight_clicks: Arc<AtomicBool>,
+    pub control: Option<Receiver<CaptureCommand>>,
+    pub follow_mouse: Option<(usize, usize)>,
+    pub privacy_masks: Arc<RwLock<Vec<PrivacyMask>>>,
+    pub window: Option<u32>,
+    // Finestre dell'interfaccia da togliere dai frame, None senza interfaccia
+    pub excluded_windows: Option<Arc<RwLock<Vec<ScreenRect>>>>,
+    pub annotations: Arc<RwLock<Vec<Annotation>>>,
+    // Annotazioni inviate come vettori ai receiver invece che disegnate nei frame
+    pub vector_annotations: Arc<AtomicBool>,
+    pub region_presets: Arc<RwLock<Vec<RegionPreset>>>,
+    // Avvisa l'interfaccia quando un preset viene attivato con il suo tasto rapido
+    pub preset_activated: Option<Sender<usize>>,
+}
+
+pub enum CaptureCommand {
+    Display(usize),
+    Area(Option<Rect>),
+    // Dimensione della finestra che segue il cursore, None per tornare all'area selezionata
+    FollowMouse(Option<(usize, usize)>),
+    // Finestra di un'applicazione da condividere (id X11), None per tornare all'area selezionata
+    Window(Option<u32>),
+}
+
+impl Default for CasterOptions {
+    fn default() -> Self {
+        Self {
+            display_index: 0,
+            selected_area: None,
+            web_addr: None,
+            rtsp_addr: None,
+            udp_enabled: false,
+            udp_simulated_loss: 0.0,
+            quic_addr: None,
+            target_fps: Arc::new(AtomicU32::new(DEFAULT_FPS)),
+            encoder_threads: 0,
+            output_scale: Arc::new(RwLock::new(OutputScale::Native)),
+            show_cursor: Arc::new(AtomicBool::new(true)),
+            highlight_clicks: Arc::new(AtomicBool::new(false)),
+            control: None,
+            follow_mouse: None,
+            privacy_masks: Arc::new(RwLock::new(Vec::new())),
+            window: None,
+            excluded_windows: None,
+            annotations: Arc::new(RwLock::new(Vec::new())),
+            vector_annotations: Arc::new(AtomicBool::new(false)),
+            region_presets: Arc::new(RwLock::new(Vec::new())),
+            preset_activated: None,
+        }
+    }
+}
 
 struct HotkeyState {
     paused: Arc<AtomicBool>,
     screen_blanked: Arc<AtomicBool>,
     terminate: Arc<AtomicBool>,
+    region_presets: Arc<RwLock<Vec<RegionPreset>>>,
+    preset_commands: Sender<CaptureCommand>,
+    preset_activated: Option<Sender<usize>>,
 }
 
 
@@ -35,6 +121,19 @@ fn handle_hotkeys(hotkey_state: Arc<HotkeyState>) {
                 hotkey_state.screen_blanked.fetch_xor(true, Ordering::SeqCst);
                 println!("Schermo {}.", if hotkey_state.screen_blanked.load(Ordering::SeqCst) { "blanked" } else { "unblanked" });
             }
+            // I tasti dei preset funzionano anche quando la finestra dell'applicazione non ha il focus
+            let pressed = keys.iter().find(|key| !last_keys.contains(key));
+            let presets = hotkey_state.region_presets.read().unwrap();
+            if let Some(index) = pressed.and_then(|key| presets.iter().position(|p| p.hotkey == Some(*key))) {
+                let preset = &presets[index];
+                println!("Preset \"{}\" attivato.", preset.name);
+                let _ = hotkey_state.preset_commands.send(CaptureCommand::Display(preset.display_index));
+                let _ = hotkey_state.preset_commands.send(CaptureCommand::Area(preset.area));
+                if let Some(preset_activated) = &hotkey_state.preset_activated {
+                    let _ = preset_activated.send(index);
+                }
+            }
+            drop(presets);
             if keys.contains(&Keycode::Escape) {
                 hotkey_state.terminate.store(true, Ordering::SeqCst);
                 println!("Terminazione richiesta.");
@@ -47,125 +146,431 @@ fn handle_hotkeys(hotkey_state: Arc<HotkeyState>) {
     }
 }
 
-async fn compress_frame_to_jpeg(frame: &[u8], width: usize, height: usize) -> Result<Vec<u8>, Box<dyn Error>> {
-    let mut img_buffer: RgbImage = ImageBuffer::new(width as u32, height as u32);
-    for (i, pixel) in img_buffer.pixels_mut().enumerate() {
-        let idx = i * 4;
-        *pixel = image::Rgb([frame[idx + 2], frame[idx + 1], frame[idx]]);
+fn send_packet(sender: &broadcast::Sender<Arc<[u8]>>, packet: Arc<[u8]>, scheduler: &mut FrameScheduler) {
+    if let Err(e) = sender.send(packet) {
+        eprintln!("Errore nell'invio del frame: {}", e);
     }
-    let img = DynamicImage::ImageRgb8(img_buffer);
-    let mut jpeg_data = Vec::new();
-    {
-        let mut encoder = image::codecs::jpeg::JpegEncoder::new(&mut jpeg_data);
-        encoder.encode_image(&img)?;
+    scheduler.mark_sent();
+}
+
+// Schermo invariato: l'ultimo frame va solo a chi si è appena collegato, gli altri ricevono
+// un keep-alive a bassa frequenza che evita il timeout di pausa.
+fn send_idle(sender: &broadcast::Sender<Arc<[u8]>>, pipeline: &EncodePipeline, new_viewer: bool, scheduler: &mut FrameScheduler) {
+    match new_viewer.then(|| pipeline.last_packet()).flatten() {
+        Some(packet) => send_packet(sender, packet, scheduler),
+        None if scheduler.heartbeat_due() => send_packet(sender, protocol::keep_alive().into(), scheduler),
+        None => {}
     }
-    Ok(jpeg_data)
 }
 
-async fn capture_screen(
-    sender: &broadcast::Sender<Vec<u8>>,
+// Riporta un'area sulla nuova risoluzione del display mantenendo le proporzioni.
+fn remap_area(area: Rect, old_size: (usize, usize), new_size: (usize, usize)) -> Rect {
+    let scale_x = new_size.0 as f32 / old_size.0.max(1) as f32;
+    let scale_y = new_size.1 as f32 / old_size.1.max(1) as f32;
+    Rect::from_min_max(
+        pos2(area.min.x * scale_x, area.min.y * scale_y),
+        pos2(area.max.x * scale_x, area.max.y * scale_y),
+    )
+}
+
+fn track_window(window: u32) -> Option<WindowTracker> {
+    match WindowTracker::new(window) {
+        Ok(tracker) => {
+            println!("Condivisione della finestra 0x{:x}", window);
+            Some(tracker)
+        }
+        Err(e) => {
+            eprintln!("Impossibile seguire la finestra 0x{:x}: {}", window, e);
+            None
+        }
+    }
+}
+
+// Le maschere sono salvate nelle coordinate del loro display, o del canvas se disegnate con tutti
+// i display: si riscalano sulla risoluzione attuale e si riportano in quelle della sorgente
+// catturata, che inizia in origin. Le maschere salvate non vengono modificate.
+fn screen_masks(masks: &[PrivacyMask], layout: &[DisplayGeometry], origin: (i32, i32), screen_masks: &mut Vec<PrivacyMask>) {
+    screen_masks.clear();
+    screen_masks.extend(masks.iter()
+        .filter_map(|mask| {
+            let display = source_geometry(layout, mask.display_index)?;
+            let mask = mask.scaled((display.width, display.height));
+            let left = (display.x - origin.0) as i64;
+            let top = (display.y - origin.1) as i64;
+            let min_x = left + mask.min_x as i64;
+            let min_y = top + mask.min_y as i64;
+            let max_x = left + mask.max_x.min(display.width) as i64;
+            let max_y = top + mask.max_y.min(display.height) as i64;
+            (max_x > 0 && max_y > 0).then(|| PrivacyMask {
+                min_x: min_x.max(0) as usize,
+                min_y: min_y.max(0) as usize,
+                max_x: max_x as usize,
+                max_y: max_y as usize,
+                ..mask
+            })
+        }));
+}
+
+// Display su cui si trova la finestra: quello che ne contiene il centro, secondo la disposizione reale dei monitor.
+fn window_display(bounds: WindowBounds, layout: &[DisplayGeometry]) -> Option<usize> {
+    display_at(layout, bounds.x + bounds.width as i32 / 2, bounds.y + bounds.height as i32 / 2)
+}
+
+// Parte della finestra che cade nella sorgente che inizia in origin; None se ne è del tutto fuori.
+fn window_crop(bounds: WindowBounds, origin: (i32, i32), width: usize, height: usize) -> Option<(usize, usize, usize, usize)> {
+    let x = (bounds.x - origin.0) as f32;
+    let y = (bounds.y - origin.1) as f32;
+    let area = Rect::from_min_max(pos2(x, y), pos2(x + bounds.width as f32, y + bounds.height as f32));
+    clip_area(area, width, height)
+}
+
+// Limita l'area selezionata al display corrente; un'area vuota o assente indica il display intero.
+fn area_bounds(area: Option<Rect>, width: usize, height: usize) -> (usize, usize, usize, usize) {
+    area.and_then(|area| clip_area(area, width, height)).unwrap_or((0, 0, width, height))
+}
+
+fn clip_area(area: Rect, width: usize, height: usize) -> Option<(usize, usize, usize, usize)> {
+    let start_x = (area.min.x.max(0.0) as usize).min(width);
+    let start_y = (area.min.y.max(0.0) as usize).min(height);
+    let end_x = (area.max.x.max(0.0) as usize).min(width);
+    let end_y = (area.max.y.max(0.0) as usize).min(height);
+    (start_x < end_x && start_y < end_y).then_some((start_x, start_y, end_x, end_y))
+}
+
+// Gira su un thread dedicato: cattura e ritaglio restano fuori dal runtime tokio, mentre
+// conversione, compressione e invio sono affidati alla pipeline.
+fn capture_screen(
+    sender: Arc<broadcast::Sender<Arc<[u8]>>>,
     stop_signal: Arc<AtomicBool>,
-    selected_area: Option<Rect>,
     hotkey_state: Arc<HotkeyState>,
-    display_index: usize
-) -> Result<(), Box<dyn Error>> {
-    let displays = Display::all()?;
-    if display_index >= displays.len() {
-        return Err("Indice del display non valido".into());
-    }
-    let display = displays.into_iter().nth(display_index).unwrap();
-    let mut capturer = Capturer::new(display)?;
-    let mut last_frame: Option<Vec<u8>> = None;
+    preset_commands: Receiver<CaptureCommand>,
+    mut options: CasterOptions,
+) -> io::Result<()> {
+    let mut screen = ScreenCapturer::open(options.display_index)?;
+    let mut selected_area = options.selected_area;
+    let control = options.control.take();
+    let mut current_display = options.display_index;
+    let mut follow_size = options.follow_mouse;
+    let mut follow = follow_size.map(|(width, height)| FollowWindow::new(width, height));
+    let mut pipeline = EncodePipeline::start(Arc::clone(&sender), options.encoder_threads);
+    let mut last_hash: Option<u64> = None;
+    let mut blank_submitted = false;
+    let mut last_scale = OutputScale::Native;
+    let mut cursor = CursorOverlay::new();
+    // Copia dell'area senza cursore, per ridisegnarlo quando scrap non restituisce frame nuovi
+    let mut clean_area: Vec<u8> = Vec::new();
+    let mut receivers = sender.receiver_count();
+    let mut scheduler = FrameScheduler::new(Arc::clone(&options.target_fps));
+    let mut layout = display_layout();
+    let mut last_layout_check = Instant::now();
+    let mut capture_failed = false;
+    let mut window = options.window.and_then(track_window);
+    let mut window_visible = true;
+    let mut background = Background::new();
+    let mut annotation_renderer = AnnotationRenderer::new();
+    let mut annotation_channel = AnnotationChannel::new();
+    // Copie riusate tra un frame e l'altro, così a regime il ciclo di cattura non alloca:
+    // le annotazioni e le loro impronte si ricalcolano solo quando cambiano
+    let mut masks: Vec<PrivacyMask> = Vec::new();
+    let mut excluded: Vec<ScreenRect> = Vec::new();
+    let mut annotations: Vec<Annotation> = Vec::new();
+    let mut annotation_footprints: Vec<ScreenRect> = Vec::new();
     while !stop_signal.load(Ordering::SeqCst) && !hotkey_state.terminate.load(Ordering::SeqCst) {
-        if hotkey_state.paused.load(Ordering::SeqCst) {
-            sleep(Duration::from_millis(100)).await;
-            continue;
+        scheduler.tick();
+
+        // Comandi inviati dall'interfaccia o dai tasti rapidi dei preset durante la trasmissione:
+        // i receiver ricevono semplicemente frame con la nuova risoluzione, senza riconnettersi
+        while let Some(command) = control.as_ref().and_then(|control| control.try_recv().ok()).or_else(|| preset_commands.try_recv().ok()) {
+            match command {
+                // Stesso display: non serve ricreare il capturer
+                CaptureCommand::Display(index) if index == current_display => {
+                    selected_area = None;
+                    window = None;
+                }
+                CaptureCommand::Display(index) => match ScreenCapturer::open(index) {
+                    Ok(new_screen) => {
+                        screen = new_screen;
+                        selected_area = None;
+                        window = None;
+                        current_display = index;
+                        follow = follow_size.map(|(width, height)| FollowWindow::new(width, height));
+                        println!("Cattura spostata su: {}", display_name(index));
+                    }
+                    Err(e) => eprintln!("Impossibile passare a {}: {}", display_name(index), e),
+                },
+                CaptureCommand::Area(area) => {
+                    selected_area = area;
+                    window = None;
+                    println!("Nuova area condivisa: {:?}", area);
+                }
+                CaptureCommand::Window(id) => {
+                    window = id.and_then(track_window);
+                    window_visible = true;
+                    if id.is_none() {
+                        println!("Condivisione della finestra terminata");
+                    }
+                }
+                CaptureCommand::FollowMouse(size) => {
+                    follow_size = size;
+                    follow = size.map(|(width, height)| FollowWindow::new(width, height));
+                    match size {
+                        Some((width, height)) => println!("Area che segue il mouse: {}x{}", width, height),
+                        None => println!("Area che segue il mouse disattivata"),
+                    }
+                }
+            }
+            last_hash = None;
+            blank_submitted = false;
+            clean_area.clear();
         }
-        let width = capturer.width();
-        let height = capturer.height();
-        let default_width = width;
-        let default_height = height;
-        match capturer.frame() {
-            Ok(frame) => {
-                println!("Frame catturato con successo, compressione in corso...");
-                let (selected_frame, cropped_width, cropped_height) = if let Some(area) = selected_area {
-                    let start_x = area.min.x as usize;
-                    let start_y = area.min.y as usize;
-                    let end_x = area.max.x as usize;
-                    let end_y = area.max.y as usize;
-                    let stride = frame.len() / height;
-                    let mut cropped_frame = Vec::new();
-                    for y in start_y..end_y {
-                        let start_index = y * stride + start_x * 4;
-                        let end_index = y * stride + end_x * 4;
-                        cropped_frame.extend_from_slice(&frame[start_index..end_index]);
+
+        // Cambio di risoluzione o monitor collegato/scollegato: il capturer va ricreato, altrimenti
+        // il ritaglio uscirebbe dal frame. Dopo un errore di cattura si riprova più spesso.
+        let check_interval = if capture_failed { LAYOUT_RETRY_INTERVAL } else { LAYOUT_CHECK_INTERVAL };
+        if last_layout_check.elapsed() >= check_interval {
+            last_layout_check = Instant::now();
+            let new_layout = display_layout();
+            if capture_failed || (!new_layout.is_empty() && new_layout != layout) {
+                let old_size = (screen.width(), screen.height());
+                let available = current_display == ALL_DISPLAYS || current_display < new_layout.len();
+                let index = if available { current_display } else { 0 };
+                match ScreenCapturer::open(index) {
+                    Ok(new_screen) => {
+                        screen = new_screen;
+                        let new_size = (screen.width(), screen.height());
+                        if index == current_display {
+                            selected_area = selected_area.map(|area| remap_area(area, old_size, new_size));
+                        } else {
+                            println!("{} non è più disponibile, cattura spostata su {}", display_name(current_display), display_name(index));
+                            selected_area = None;
+                            current_display = index;
+                        }
+                        follow = follow_size.map(|(width, height)| FollowWindow::new(width, height));
+                        println!("{} riconfigurato: {}x{} -> {}x{}", display_name(index), old_size.0, old_size.1, new_size.0, new_size.1);
+                        layout = new_layout;
+                        capture_failed = false;
+                        last_hash = None;
+                        blank_submitted = false;
+                        clean_area.clear();
                     }
-                    (cropped_frame, end_x - start_x, end_y - start_y)
-                } else {
-                    let stride = frame.len() / height;
-                    let mut full_frame = Vec::new();
-                    for y in 0..height {
-                        let start = y * stride;
-                        let end = start + width * 4;
-                        full_frame.extend_from_slice(&frame[start..end]);
+                    Err(e) => {
+                        eprintln!("Impossibile riaprire {}: {}", display_name(index), e);
+                        capture_failed = true;
+                        continue;
                     }
-                    (full_frame, width, height)
-                };
-                let jpeg_frame = if hotkey_state.screen_blanked.load(Ordering::SeqCst) {
-                    let blank_frame = vec![0; cropped_width * cropped_height * 4];
-                    compress_frame_to_jpeg(&blank_frame, cropped_width, cropped_height).await?
-                } else {
-                    compress_frame_to_jpeg(&selected_frame, cropped_width, cropped_height).await?
-                };
-                last_frame = Some(jpeg_frame.clone());
-                let frame_size = (jpeg_frame.len() as u32).to_be_bytes();
-                if let Err(e) = sender.send([&frame_size[..], &jpeg_frame[..]].concat()) {
-                    eprintln!("Errore nell'invio del frame: {}", e);
                 }
-            },
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                if hotkey_state.screen_blanked.load(Ordering::SeqCst) {
-                    let jpeg_frame = if let Some(ref frame) = last_frame {
-                        frame.clone()
-                    } else {
-                        let blank_frame = vec![0; default_width * default_height * 4];
-                        compress_frame_to_jpeg(&blank_frame, default_width, default_height).await?
-                    };
-                    let frame_size = (jpeg_frame.len() as u32).to_be_bytes();
-                    if let Err(e) = sender.send([&frame_size[..], &jpeg_frame[..]].concat()) {
-                        eprintln!("Errore nell'invio del frame di blank screen: {}", e);
+            }
+        }
+
+        if hotkey_state.paused.load(Ordering::SeqCst) {
+            continue;
+        }
+
+        // Un nuovo iscritto al canale non ha ancora un'immagine da mostrare
+        let new_viewer = sender.receiver_count() > receivers;
+        receivers = sender.receiver_count();
+
+        // La finestra condivisa si legge a ogni frame: se è passata su un altro display la cattura la segue
+        let window_bounds = window.as_ref().map(|tracker| tracker.bounds());
+        if let Some(Some(bounds)) = window_bounds.filter(|_| current_display != ALL_DISPLAYS) {
+            if let Some(index) = window_display(bounds, &layout).filter(|&index| index != current_display) {
+                match ScreenCapturer::open(index) {
+                    Ok(new_screen) => {
+                        screen = new_screen;
+                        current_display = index;
+                        last_hash = None;
+                        clean_area.clear();
+                        println!("La finestra condivisa è passata sul display {}", index + 1);
                     }
-                } else if let Some(ref frame) = last_frame {
-                    let frame_size = (frame.len() as u32).to_be_bytes();
-                    if let Err(e) = sender.send([&frame_size[..], &frame[..]].concat()) {
-                        eprintln!("Errore nell'invio del frame di heartbeat: {}", e);
+                    Err(e) => eprintln!("Impossibile passare al display {}: {}", index + 1, e),
+                }
+            }
+        }
+        let width = screen.width();
+        let height = screen.height();
+        let (display_x, display_y) = screen.origin();
+        // Finestra chiusa, ridotta a icona o del tutto fuori dalla sorgente: i receiver restano
+        // sull'ultimo frame, senza mostrare il resto dello schermo
+        let window_crop = window_bounds.map(|bounds| bounds.and_then(|bounds| window_crop(bounds, (display_x, display_y), width, height)));
+        if let Some(None) = window_crop {
+            if window_visible {
+                println!("La finestra condivisa non è visibile, trasmissione sospesa");
+                window_visible = false;
+            }
+            send_idle(&sender, &pipeline, new_viewer, &mut scheduler);
+            continue;
+        }
+        window_visible = true;
+
+        let (start_x, start_y, end_x, end_y) = match (follow.as_mut(), window_crop.flatten()) {
+            (Some(follow), _) => {
+                let (mouse_x, mouse_y) = cursor.mouse_position();
+                follow.update((mouse_x - display_x, mouse_y - display_y), width, height)
+            }
+            (None, Some(crop)) => crop,
+            (None, None) => area_bounds(selected_area, width, height),
+        };
+        let (selected_width, selected_height) = (end_x - start_x, end_y - start_y);
+
+        // La scala può cambiare durante la trasmissione: il frame corrente va ricompresso
+        let scale = *options.output_scale.read().unwrap();
+        if scale != last_scale {
+            println!("Risoluzione in uscita: {:?}", scale);
+            last_scale = scale;
+            last_hash = None;
+            blank_submitted = false;
+        }
+
+        // Schermo oscurato: il frame nero viene compresso una sola volta
+        if hotkey_state.screen_blanked.load(Ordering::SeqCst) {
+            last_hash = None;
+            if !blank_submitted {
+                let mut black = pipeline.take_buffer();
+                black.resize(selected_width * selected_height * 4, 0);
+                let output_size = scale.output_size(selected_width, selected_height);
+                let black = RawFrame { bgra: black, width: selected_width, height: selected_height, output_size };
+                blank_submitted = pipeline.submit(black);
+                scheduler.mark_sent();
+            } else {
+                send_idle(&sender, &pipeline, new_viewer, &mut scheduler);
+            }
+            continue;
+        }
+        blank_submitted = false;
+
+        let show_cursor = options.show_cursor.load(Ordering::SeqCst);
+        screen_masks(&options.privacy_masks.read().unwrap(), &layout, (display_x, display_y), &mut masks);
+        // Cursore e maschere vanno ridisegnati anche quando scrap non restituisce frame nuovi
+        let keep_clean = show_cursor || !masks.is_empty();
+        if !keep_clean {
+            clean_area.clear();
+        }
+
+        {
+            let current = options.annotations.read().unwrap();
+            if *current != annotations {
+                annotations.clone_from(&current);
+                annotation_footprints.clear();
+                for annotation in &annotations {
+                    annotation.footprint(&mut annotation_footprints);
+                }
+            }
+        }
+        let vector_annotations = options.vector_annotations.load(Ordering::SeqCst);
+
+        let mut selected_frame = pipeline.take_buffer();
+        let captured = screen.with_frame(|frame, stride| {
+            for y in start_y..end_y {
+                selected_frame.extend_from_slice(&frame[y * stride + start_x * 4..y * stride + end_x * 4]);
+            }
+        });
+        match captured {
+            Ok(()) => {
+                if let Some(excluded_windows) = &options.excluded_windows {
+                    excluded.clear();
+                    excluded.extend_from_slice(&excluded_windows.read().unwrap());
+                    // Con le annotazioni vettoriali anche la loro immagine nella finestra trasparente va tolta
+                    if vector_annotations {
+                        excluded.extend_from_slice(&annotation_footprints);
                     }
+                    let origin = (display_x + start_x as i32, display_y + start_y as i32);
+                    background.apply(&mut selected_frame, selected_width, selected_height, origin, &excluded);
+                }
+                if keep_clean {
+                    clean_area.clear();
+                    clean_area.extend_from_slice(&selected_frame);
                 }
-                sleep(Duration::from_millis(10)).await;
+            },
+            // Nessun frame nuovo, ma il cursore può essersi mosso: si ridisegna sull'ultima cattura
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && !clean_area.is_empty() => {
+                selected_frame.extend_from_slice(&clean_area);
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                pipeline.recycle(selected_frame);
+                send_idle(&sender, &pipeline, new_viewer, &mut scheduler);
+                continue;
+            },
+            // Frame più piccolo di quanto atteso: il display è cambiato prima che il controllo se ne accorgesse
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {
+                eprintln!("Dimensione del frame inattesa, il display verrà riaperto.");
+                capture_failed = true;
+                pipeline.recycle(selected_frame);
+                continue;
             },
             Err(e) => {
                 eprintln!("Errore nella cattura del frame: {:?}", e);
+                capture_failed = true;
+                pipeline.recycle(selected_frame);
+                continue;
             }
         }
-        sleep(Duration::from_millis(10)).await;
+
+        apply_masks(&mut selected_frame, selected_width, selected_height, (start_x, start_y), &masks);
+        // Le annotazioni vanno sopra le maschere: sono del presentatore, non contenuto da nascondere
+        let origin = (display_x + start_x as i32, display_y + start_y as i32);
+        // Disattivando i vettori il canale riceve un elenco vuoto e i receiver cancellano i propri
+        let sent_annotations: &[Annotation] = if vector_annotations { &annotations } else { &[] };
+        if let Some(packet) = annotation_channel.update(sent_annotations, origin, (selected_width, selected_height), new_viewer) {
+            let _ = sender.send(packet.into());
+        }
+        if !vector_annotations {
+            annotation_renderer.draw(&mut selected_frame, selected_width, selected_height, origin, &annotations);
+        }
+        if show_cursor {
+            cursor.draw(&mut selected_frame, selected_width, selected_height, origin, options.highlight_clicks.load(Ordering::SeqCst));
+        }
+
+        // Si confrontano solo i pixel dell'area condivisa: se non sono cambiati la compressione viene saltata
+        let hash = xxh3_64(&selected_frame);
+        if last_hash == Some(hash) {
+            pipeline.recycle(selected_frame);
+            send_idle(&sender, &pipeline, new_viewer, &mut scheduler);
+            continue;
+        }
+
+        // Con la pipeline piena il frame viene scartato e ritentato al prossimo tick
+        let output_size = scale.output_size(selected_width, selected_height);
+        if pipeline.submit(RawFrame { bgra: selected_frame, width: selected_width, height: selected_height, output_size }) {
+            println!("Frame catturato con successo, compressione in corso...");
+            last_hash = Some(hash);
+            scheduler.mark_sent();
+        }
     }
+    pipeline.finish();
     println!("Cattura dello schermo interrotta.");
     Ok(())
 }
 
 
 
-pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, selected_area: Option<Rect>,display_index: usize,paused: Arc<AtomicBool>, screen_blanked: Arc<AtomicBool>,terminate: Arc<AtomicBool>) -> Result<(), Box<dyn Error>> {
+pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, paused: Arc<AtomicBool>, screen_blanked: Arc<AtomicBool>,terminate: Arc<AtomicBool>, options: CasterOptions) -> Result<(), Box<dyn Error>> {
     let listener = TcpListener::bind(addr).await?;
-    let (tx, _rx) = broadcast::channel::<Vec<u8>>(100);
+    let (tx, _rx) = broadcast::channel::<Arc<[u8]>>(100);
     let tx = Arc::new(tx);
     println!("Caster avviato su {}", addr);
+    let (preset_tx, preset_rx) = mpsc::channel();
     let hotkey_state = Arc::new(HotkeyState {
         paused,
         screen_blanked,
         terminate,
+        region_presets: Arc::clone(&options.region_presets),
+        preset_commands: preset_tx,
+        preset_activated: options.preset_activated.clone(),
     });
 
+    if let Some(ref web_addr) = options.web_addr {
+        web::start_web_server(web_addr, Arc::clone(&tx), Arc::clone(&stop_signal), Arc::clone(&hotkey_state.terminate)).await?;
+    }
+    if options.udp_enabled {
+        udp::start_udp_server(addr, Arc::clone(&tx), Arc::clone(&stop_signal), Arc::clone(&hotkey_state.terminate), options.udp_simulated_loss).await?;
+    }
+    if let Some(ref quic_addr) = options.quic_addr {
+        quic::start_quic_server(quic_addr, Arc::clone(&tx), Arc::clone(&stop_signal), Arc::clone(&hotkey_state.terminate)).await?;
+    }
+    if let Some(ref rtsp_addr) = options.rtsp_addr {
+        rtsp::start_rtsp_server(rtsp_addr, Arc::clone(&tx), Arc::clone(&stop_signal), Arc::clone(&hotkey_state.terminate)).await?;
+    }
+
     let hotkey_state_clone = Arc::clone(&hotkey_state);
     std::thread::spawn(move || {
         handle_hotkeys(hotkey_state_clone);
@@ -177,7 +582,12 @@ pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, selected_are
 
     tokio::spawn(async move {
         while !stop_signal_clone.load(Ordering::SeqCst) && !hotkey_state_clone.terminate.load(Ordering::SeqCst) {
-            if let Ok((mut socket, addr)) = listener.accept().await {
+            // Il flag di stop si ricontrolla anche senza nuovi client, così dopo Stop la porta viene liberata subito
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = sleep(STOP_CHECK_INTERVAL) => continue,
+            };
+            if let Ok((mut socket, addr)) = accepted {
                 println!("Nuova connessione da: {}", addr);
                 let mut rx = tx_clone.subscribe();
                 let stop_signal_client = Arc::clone(&stop_signal_clone);
@@ -192,8 +602,8 @@ pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, selected_are
                                 }
                                 let frame_size_bytes = &frame[0..4];
                                 let frame_data = &frame[4..];
-                                if socket.write_all(&frame_size_bytes).await.is_err() ||
-                                    socket.write_all(&frame_data).await.is_err() {
+                                if socket.write_all(frame_size_bytes).await.is_err() ||
+                                    socket.write_all(frame_data).await.is_err() {
                                     eprintln!("Errore nell'invio del frame al client {}", addr);
                                     break;
                                 }
@@ -214,17 +624,123 @@ pub async fn start_caster(addr: &str, stop_signal: Arc<AtomicBool>, selected_are
         }
 
         // Invia un segnale esplicito di chiusura ai receiver
-        if let Err(_) = tx_clone.send(vec![0, 0, 0, 0]) {
+        if tx_clone.send(protocol::end_of_stream().into()).is_err() {
             eprintln!("Errore nell'invio del segnale di terminazione.");
         }
 
         println!("Listener TCP interrotto.");
     });
 
-    capture_screen(&*Arc::clone(&tx), stop_signal, selected_area, Arc::clone(&hotkey_state),display_index).await?;
+    let tx_capture = Arc::clone(&tx);
+    let hotkey_state_capture = Arc::clone(&hotkey_state);
+    tokio::task::spawn_blocking(move || {
+        capture_screen(tx_capture, stop_signal, hotkey_state_capture, preset_rx, options)
+    }).await??;
     println!("Caster completamente fermato.");
     hotkey_state.screen_blanked.store(false, Ordering::SeqCst);
     hotkey_state.paused.store(false, Ordering::SeqCst);
     hotkey_state.terminate.store(false, Ordering::SeqCst);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Portatile sotto un monitor più grande, spostato a destra
+    const LAYOUT: [DisplayGeometry; 2] = [
+        DisplayGeometry { x: 320, y: 1440, width: 1920, height: 1080 },
+        DisplayGeometry { x: 0, y: 0, width: 2560, height: 1440 },
+    ];
+
+    fn crop(bounds: WindowBounds, display: DisplayGeometry) -> Option<(usize, usize, usize, usize)> {
+        window_crop(bounds, (display.x, display.y), display.width, display.height)
+    }
+
+    #[test]
+    fn window_on_a_stacked_display() {
+        let window = WindowBounds { x: 420, y: 1540, width: 800, height: 600 };
+        assert_eq!(window_display(window, &LAYOUT), Some(0));
+        assert_eq!(crop(window, LAYOUT[0]), Some((100, 100, 900, 700)));
+        // Con tutti i display la finestra resta nelle coordinate dello schermo virtuale
+        let canvas = source_geometry(&LAYOUT, ALL_DISPLAYS).unwrap();
+        assert_eq!(crop(window, canvas), Some((420, 1540, 1220, 2140)));
+    }
+
+    #[test]
+    fn window_across_displays_is_clipped() {
+        // Il centro è sul monitor in alto, la parte che sborda su quello in basso viene tagliata
+        let window = WindowBounds { x: 2000, y: 1000, width: 800, height: 600 };
+        assert_eq!(window_display(window, &LAYOUT), Some(1));
+        assert_eq!(crop(window, LAYOUT[1]), Some((2000, 1000, 2560, 1440)));
+        // Finestra con il centro fuori da ogni monitor: la cattura resta dov'è e non mostra nulla
+        let window = WindowBounds { x: 0, y: 1600, width: 200, height: 200 };
+        assert_eq!(window_display(window, &LAYOUT), None);
+        assert_eq!(crop(window, LAYOUT[0]), None);
+        assert_eq!(crop(window, LAYOUT[1]), None);
+    }
+
+    #[test]
+    fn window_outside_the_source_is_not_shared() {
+        // Finestra tutta sul monitor in alto mentre si cattura il portatile
+        let window = WindowBounds { x: 100, y: 100, width: 800, height: 600 };
+        assert_eq!(crop(window, LAYOUT[0]), None);
+        // Finestra fuori dal canvas di tutti i display
+        let canvas = source_geometry(&LAYOUT, ALL_DISPLAYS).unwrap();
+        let window = WindowBounds { x: 2600, y: 1500, width: 300, height: 200 };
+        assert_eq!(crop(window, canvas), None);
+        // L'area selezionata vuota indica invece il display intero
+        assert_eq!(area_bounds(Some(Rect::from_min_max(pos2(3000.0, 0.0), pos2(3100.0, 50.0))), 2560, 1440), (0, 0, 2560, 1440));
+    }
+
+    #[test]
+    fn masks_follow_the_display_position() {
+        let mask = PrivacyMask {
+            display_index: 0,
+            min_x: 10,
+            min_y: 20,
+            max_x: 110,
+            max_y: 120,
+            mode: crate::masks::MaskMode::Fill,
+            reference: Some((3840, 2160)),
+        };
+        let mut masks = Vec::new();
+        screen_masks(&[mask], &LAYOUT, (0, 0), &mut masks);
+        // Disegnata a 4K e riscalata a metà, poi spostata nella posizione del display nel canvas
+        assert_eq!((masks[0].min_x, masks[0].min_y, masks[0].max_x, masks[0].max_y), (325, 1450, 375, 1500));
+        screen_masks(&[mask], &LAYOUT, (LAYOUT[0].x, LAYOUT[0].y), &mut masks);
+        assert_eq!((masks[0].min_x, masks[0].min_y, masks[0].max_x, masks[0].max_y), (5, 10, 55, 60));
+        // Maschera di un display non catturato, tutta a sinistra o sopra la sorgente
+        screen_masks(&[mask], &LAYOUT, (2000, 2000), &mut masks);
+        assert!(masks.is_empty());
+    }
+
+    #[test]
+    fn relayouts_do_not_change_the_saved_masks() {
+        let saved = vec![PrivacyMask {
+            display_index: 1,
+            min_x: 101,
+            min_y: 51,
+            max_x: 303,
+            max_y: 153,
+            mode: crate::masks::MaskMode::Fill,
+            reference: Some((1920, 1080)),
+        }];
+        let original = saved.clone();
+        let smaller = [
+            DisplayGeometry { x: 0, y: 0, width: 1280, height: 720 },
+            DisplayGeometry { x: 1280, y: 0, width: 1024, height: 576 },
+        ];
+        let mut first = Vec::new();
+        screen_masks(&saved, &LAYOUT, (0, 0), &mut first);
+        // Più cambi di disposizione di fila partono sempre dalle maschere salvate
+        let mut masks = Vec::new();
+        for layout in [&smaller, &LAYOUT, &smaller, &smaller] {
+            screen_masks(&saved, layout, (0, 0), &mut masks);
+            assert_eq!(saved, original);
+        }
+        screen_masks(&saved, &LAYOUT, (0, 0), &mut masks);
+        assert_eq!(masks, first);
+        assert_eq!((first[0].min_x, first[0].min_y, first[0].max_x, first[0].max_y), (134, 68, 404, 204));
+    }
+}
@@ -0,0 +1,49 @@
+// Conversione dei frame BGRA catturati da scrap nel formato RGB compatto richiesto dall'encoder JPEG.
+// Il buffer di destinazione viene riutilizzato tra un frame e l'altro, quindi a regime non alloca.
+
+pub fn bgra_to_rgb(src: &[u8], stride: usize, width: usize, height: usize, dst: &mut Vec<u8>) {
+    // Il buffer viene sovrascritto per intero: resize azzera solo l'eventuale parte nuova
+    dst.resize(width * height * 3, 0);
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("ssse3") {
+        for (src_row, dst_row) in src.chunks(stride).take(height).zip(dst.chunks_exact_mut(width * 3)) {
+            // SAFETY: il supporto SSSE3 è stato verificato a runtime
+            unsafe { convert_row_ssse3(&src_row[..width * 4], dst_row) };
+        }
+        return;
+    }
+
+    for (src_row, dst_row) in src.chunks(stride).take(height).zip(dst.chunks_exact_mut(width * 3)) {
+        convert_row(&src_row[..width * 4], dst_row);
+    }
+}
+
+fn convert_row(src: &[u8], dst: &mut [u8]) {
+    for (bgra, rgb) in src.chunks_exact(4).zip(dst.chunks_exact_mut(3)) {
+        rgb[0] = bgra[2];
+        rgb[1] = bgra[1];
+        rgb[2] = bgra[0];
+    }
+}
+
+// Converte 4 pixel per istruzione con uno shuffle: ogni store scrive 16 byte di cui 12 validi,
+// quindi il ciclo si ferma quando in uscita restano meno di 16 byte e la coda va al percorso scalare.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "ssse3")]
+unsafe fn convert_row_ssse3(src: &[u8], dst: &mut [u8]) {
+    use std::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_setr_epi8, _mm_shuffle_epi8, _mm_storeu_si128};
+
+    let mask = _mm_setr_epi8(2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1);
+    let pixels = src.len() / 4;
+    let mut i = 0;
+    while i + 4 <= pixels && i * 3 + 16 <= dst.len() {
+        let bgra = _mm_loadu_si128(src.as_ptr().add(i * 4) as *const __m128i);
+        _mm_storeu_si128(dst.as_mut_ptr().add(i * 3) as *mut __m128i, _mm_shuffle_epi8(bgra, mask));
+        i += 4;
+    }
+    convert_row(&src[i * 4..], &mut dst[i * 3..]);
+}
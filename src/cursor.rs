@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+use device_query::{DeviceQuery, DeviceState};
+
+// scrap non include il puntatore nei frame: la posizione viene letta con device_query e
+// il cursore viene disegnato nel frame BGRA prima della compressione, così lo vedono tutti
+// i receiver (anche browser e client RTSP) senza messaggi aggiuntivi.
+
+// 'X' bordo nero, '.' riempimento bianco, ' ' trasparente. La punta è nell'angolo in alto a sinistra.
+const ARROW: [&str; 20] = [
+    "X",
+    "XX",
+    "X.X",
+    "X..X",
+    "X...X",
+    "X....X",
+    "X.....X",
+    "X......X",
+    "X.......X",
+    "X........X",
+    "X.........X",
+    "X..........X",
+    "X......XXXXX",
+    "X...X..X",
+    "X..XX..X",
+    "X.X  X..X",
+    "XX   X..X",
+    "X     X..X",
+    "      X..X",
+    "       XX",
+];
+
+const RIPPLE_DURATION: Duration = Duration::from_millis(500);
+const RIPPLE_MAX_RADIUS: f32 = 28.0;
+const RIPPLE_THICKNESS: f32 = 3.0;
+const RIPPLE_COLOR: [u8; 3] = [0, 200, 255]; // BGR, giallo
+
+pub struct CursorOverlay {
+    device_state: DeviceState,
+    was_pressed: bool,
+    ripples: Vec<(i32, i32, Instant)>,
+}
+
+impl CursorOverlay {
+    pub fn new() -> Self {
+        Self {
+            device_state: DeviceState::new(),
+            was_pressed: false,
+            ripples: Vec::new(),
+        }
+    }
+
+    // Posizione del cursore in coordinate del desktop.
+    pub fn mouse_position(&self) -> (i32, i32) {
+        self.device_state.get_mouse().coords
+    }
+
+    // origin è la posizione, in coordinate del desktop, del pixel in alto a sinistra del frame.
+    pub fn draw(&mut self, bgra: &mut [u8], width: usize, height: usize, origin: (i32, i32), highlight_clicks: bool) {
+        let mouse = self.device_state.get_mouse();
+        let x = mouse.coords.0 - origin.0;
+        let y = mouse.coords.1 - origin.1;
+
+        // Il pulsante 0 non è usato da device_query: 1 sinistro, 2 destro, 3 centrale
+        let pressed = mouse.button_pressed.iter().skip(1).any(|&p| p);
+        if highlight_clicks && pressed && !self.was_pressed {
+            self.ripples.push((x, y, Instant::now()));
+        }
+        self.was_pressed = pressed;
+        self.ripples.retain(|(_, _, start)| start.elapsed() < RIPPLE_DURATION);
+        if !highlight_clicks {
+            self.ripples.clear();
+        }
+
+        for &(rx, ry, start) in &self.ripples {
+            let progress = start.elapsed().as_secs_f32() / RIPPLE_DURATION.as_secs_f32();
+            draw_ring(bgra, width, height, rx, ry, 6.0 + (RIPPLE_MAX_RADIUS - 6.0) * progress, 0.7 * (1.0 - progress));
+        }
+
+        for (row, line) in ARROW.iter().enumerate() {
+            for (col, symbol) in line.bytes().enumerate() {
+                let color = match symbol {
+                    b'X' => 0,
+                    b'.' => 255,
+                    _ => continue,
+                };
+                let (px, py) = (x + col as i32, y + row as i32);
+                if px < 0 || py < 0 || px as usize >= width || py as usize >= height {
+                    continue;
+                }
+                let idx = (py as usize * width + px as usize) * 4;
+                bgra[idx..idx + 3].fill(color);
+            }
+        }
+    }
+}
+
+fn draw_ring(bgra: &mut [u8], width: usize, height: usize, cx: i32, cy: i32, radius: f32, alpha: f32) {
+    let extent = (radius + RIPPLE_THICKNESS).ceil() as i32;
+    for py in (cy - extent).max(0)..(cy + extent + 1).min(height as i32) {
+        for px in (cx - extent).max(0)..(cx + extent + 1).min(width as i32) {
+            let distance = (((px - cx) * (px - cx) + (py - cy) * (py - cy)) as f32).sqrt();
+            if (distance - radius).abs() > RIPPLE_THICKNESS / 2.0 {
+                continue;
+            }
+            let idx = (py as usize * width + px as usize) * 4;
+            for (channel, &color) in bgra[idx..idx + 3].iter_mut().zip(RIPPLE_COLOR.iter()) {
+                *channel = (*channel as f32 * (1.0 - alpha) + color as f32 * alpha) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// Finestre dell'applicazione (pannello del caster, barra degli strumenti, selezione dell'area)
+// da non mostrare ai receiver. scrap cattura tutto lo schermo, quindi l'interfaccia pubblica
+// la posizione delle proprie finestre e il caster vi ricopia l'ultimo contenuto visto sotto
+// di esse: chi guarda vede un'immagine ferma in quella zona, mai i controlli.
+
+// Rettangolo in pixel fisici dello schermo virtuale, come le coordinate del mouse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenRect {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+// Ultimo contenuto dell'area condivisa senza le finestre escluse.
+pub struct Background {
+    pixels: Vec<u8>,
+    width: usize,
+    height: usize,
+    origin: (i32, i32),
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Self { pixels: Vec::new(), width: 0, height: 0, origin: (0, 0) }
+    }
+
+    // Ricopre le finestre escluse con il contenuto salvato e aggiorna la copia con il frame.
+    // origin è la posizione del frame (BGRA compatto) nello schermo virtuale. La copia si legge
+    // in coordinate dello schermo, così resta utile anche quando l'area condivisa si sposta o
+    // cambia dimensione; le parti delle finestre che la copia non copre (primo frame, area nuova)
+    // vengono riempite di nero: le finestre escluse non vanno mai inviate.
+    pub fn apply(&mut self, bgra: &mut [u8], width: usize, height: usize, origin: (i32, i32), excluded: &[ScreenRect]) {
+        for rect in excluded {
+            let min_x = (rect.min_x - origin.0).clamp(0, width as i32) as usize;
+            let max_x = (rect.max_x - origin.0).clamp(0, width as i32) as usize;
+            let min_y = (rect.min_y - origin.1).clamp(0, height as i32) as usize;
+            let max_y = (rect.max_y - origin.1).clamp(0, height as i32) as usize;
+            if min_x >= max_x || min_y >= max_y {
+                continue;
+            }
+            for y in min_y..max_y {
+                bgra[(y * width + min_x) * 4..(y * width + max_x) * 4].fill(0);
+                // Parte della riga coperta dalla copia, in coordinate della copia
+                let saved_y = y as i32 + origin.1 - self.origin.1;
+                let saved_min_x = (min_x as i32 + origin.0 - self.origin.0).max(0);
+                let saved_max_x = (max_x as i32 + origin.0 - self.origin.0).min(self.width as i32);
+                if saved_y < 0 || saved_y >= self.height as i32 || saved_min_x >= saved_max_x {
+                    continue;
+                }
+                let start_x = (saved_min_x + self.origin.0 - origin.0) as usize;
+                let end_x = (saved_max_x + self.origin.0 - origin.0) as usize;
+                let saved_row = saved_y as usize * self.width;
+                bgra[(y * width + start_x) * 4..(y * width + end_x) * 4]
+                    .copy_from_slice(&self.pixels[(saved_row + saved_min_x as usize) * 4..(saved_row + saved_max_x as usize) * 4]);
+            }
+        }
+        // Dentro le finestre il frame contiene ormai il vecchio contenuto (o il nero), fuori quello nuovo
+        self.pixels.clear();
+        self.pixels.extend_from_slice(bgra);
+        self.width = width;
+        self.height = height;
+        self.origin = origin;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: ScreenRect = ScreenRect { min_x: 2, min_y: 1, max_x: 4, max_y: 3 };
+
+    // Frame 4x4 in cui ogni pixel vale value
+    fn frame(value: u8) -> Vec<u8> {
+        vec![value; 4 * 4 * 4]
+    }
+
+    fn pixel(bgra: &[u8], width: usize, x: usize, y: usize) -> u8 {
+        bgra[(y * width + x) * 4]
+    }
+
+    #[test]
+    fn first_frame_is_never_sent_with_the_window() {
+        let mut background = Background::new();
+        let mut first = frame(200);
+        background.apply(&mut first, 4, 4, (0, 0), &[WINDOW]);
+        assert_eq!(pixel(&first, 4, 2, 1), 0);
+        assert_eq!(pixel(&first, 4, 1, 1), 200);
+        // Al frame successivo la zona resta quella salvata, non il nuovo contenuto della finestra
+        let mut second = frame(100);
+        background.apply(&mut second, 4, 4, (0, 0), &[WINDOW]);
+        assert_eq!(pixel(&second, 4, 3, 2), 0);
+        assert_eq!(pixel(&second, 4, 0, 0), 100);
+    }
+
+    #[test]
+    fn moved_area_reuses_the_overlapping_background() {
+        let mut background = Background::new();
+        let mut first = frame(50);
+        background.apply(&mut first, 4, 4, (0, 0), &[]);
+        // L'area si sposta di due pixel a destra: la finestra (2..4, 1..3) è ora nelle colonne 0..2,
+        // ancora coperte dalla copia; le colonne oltre il vecchio bordo non lo sarebbero
+        let mut moved = frame(120);
+        let window = ScreenRect { min_x: 2, min_y: 1, max_x: 6, max_y: 3 };
+        background.apply(&mut moved, 4, 4, (2, 0), &[window]);
+        assert_eq!(pixel(&moved, 4, 0, 1), 50);
+        assert_eq!(pixel(&moved, 4, 1, 2), 50);
+        assert_eq!(pixel(&moved, 4, 2, 1), 0);
+        assert_eq!(pixel(&moved, 4, 3, 2), 0);
+        assert_eq!(pixel(&moved, 4, 0, 0), 120);
+        // Con una dimensione diversa vale lo stesso, in coordinate dello schermo
+        let mut resized = vec![90; 2 * 2 * 4];
+        background.apply(&mut resized, 2, 2, (2, 1), &[WINDOW]);
+        assert_eq!(pixel(&resized, 2, 0, 0), 50);
+    }
+}
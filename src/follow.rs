@@ -0,0 +1,67 @@
+// Finestra di cattura di dimensione fissa che segue il cursore: resta ferma finché il
+// puntatore si muove nella zona centrale e poi lo raggiunge gradualmente.
+
+// Frazione centrale della finestra in cui il cursore si muove senza spostarla
+const DEAD_ZONE: f32 = 0.6;
+// Frazione della distanza dalla posizione obiettivo percorsa a ogni frame
+const EASING: f32 = 0.2;
+
+pub struct FollowWindow {
+    width: usize,
+    height: usize,
+    center: Option<(f32, f32)>,
+}
+
+impl FollowWindow {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width: width.max(1),
+            height: height.max(1),
+            center: None,
+        }
+    }
+
+    // Riceve il cursore in coordinate del display e restituisce (start_x, start_y, end_x, end_y).
+    pub fn update(&mut self, mouse: (i32, i32), display_width: usize, display_height: usize) -> (usize, usize, usize, usize) {
+        let width = self.width.min(display_width);
+        let height = self.height.min(display_height);
+        let (mouse_x, mouse_y) = (mouse.0 as f32, mouse.1 as f32);
+
+        let (center_x, center_y) = match self.center {
+            // Prima posizione: la finestra parte già centrata sul cursore
+            None => (mouse_x, mouse_y),
+            Some((x, y)) => (
+                ease(x, follow_axis(x, mouse_x, width as f32 * DEAD_ZONE / 2.0)),
+                ease(y, follow_axis(y, mouse_y, height as f32 * DEAD_ZONE / 2.0)),
+            ),
+        };
+        let (half_width, half_height) = (width as f32 / 2.0, height as f32 / 2.0);
+        let center_x = center_x.clamp(half_width, display_width as f32 - half_width);
+        let center_y = center_y.clamp(half_height, display_height as f32 - half_height);
+        self.center = Some((center_x, center_y));
+
+        let start_x = ((center_x - half_width).round() as usize).min(display_width - width);
+        let start_y = ((center_y - half_height).round() as usize).min(display_height - height);
+        (start_x, start_y, start_x + width, start_y + height)
+    }
+}
+
+// Posizione obiettivo del centro: il minimo spostamento che riporta il cursore nella zona morta.
+fn follow_axis(center: f32, mouse: f32, half_dead_zone: f32) -> f32 {
+    if mouse > center + half_dead_zone {
+        mouse - half_dead_zone
+    } else if mouse < center - half_dead_zone {
+        mouse + half_dead_zone
+    } else {
+        center
+    }
+}
+
+// Sotto il mezzo pixel la finestra si ferma, così a cursore immobile i frame restano identici.
+fn ease(current: f32, target: f32) -> f32 {
+    if (target - current).abs() < 0.5 {
+        target
+    } else {
+        current + (target - current) * EASING
+    }
+}
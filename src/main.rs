@@ -1,13 +1,129 @@
 use std::error::Error;
 use std::env;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use eframe::egui::ViewportBuilder;
 
+mod annotations;
 mod caster;
+mod convert;
+mod cursor;
+mod exclusion;
+mod follow;
+mod masks;
+mod pipeline;
+mod presets;
+mod protocol;
+mod quic;
 mod receiver;
+mod rtsp;
+mod scaling;
+mod scheduler;
+mod screen;
+mod selection;
+mod udp;
 mod ui;
+mod web;
+mod window_share;
 
 use ui::MyApp;
 
+const CASTER_USAGE: &str = "caster <indirizzo> [--display <indice|all>] [--web <indirizzo>] [--rtsp <indirizzo>] [--udp] [--udp-loss <percentuale>] [--quic <indirizzo>] [--fps <frame al secondo>] [--encoder-threads <numero>] [--scale <nativa|larghezza|percentuale%|LxA>] [--no-cursor] [--click-highlight] [--follow-mouse <LxA>] [--preset <nome>] [--window <id|titolo>]";
+
+const RECEIVER_USAGE: &str = "receiver <indirizzo> [--transport <tcp|udp|quic>]";
+
+fn parse_caster_args(args: &[String]) -> Result<(String, caster::CasterOptions), String> {
+    let mut args = args.iter();
+    let addr = args.next().ok_or("Indirizzo del caster mancante")?.clone();
+    let mut options = caster::CasterOptions::default();
+    // Le maschere privacy salvate dall'interfaccia valgono anche per il caster da riga di comando
+    *options.privacy_masks.write().unwrap() = masks::load_masks();
+    *options.region_presets.write().unwrap() = presets::load_presets();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(format!("Valore mancante per {}", arg));
+        match arg.as_str() {
+            "--display" => {
+                // "all" cattura tutti i display affiancati in un'unica immagine
+                options.display_index = match value()?.as_str() {
+                    "all" => screen::ALL_DISPLAYS,
+                    index => index.parse().map_err(|_| "Indice del display non valido".to_string())?,
+                };
+            }
+            "--preset" => {
+                let name = value()?;
+                let region_presets = options.region_presets.read().unwrap();
+                let index = presets::find_preset(&region_presets, &name).ok_or(format!("Preset non trovato: {}", name))?;
+                let (display_index, area) = (region_presets[index].display_index, region_presets[index].area);
+                drop(region_presets);
+                options.display_index = display_index;
+                options.selected_area = area;
+            }
+            "--window" => {
+                let query = value()?;
+                let windows = window_share::list_windows();
+                let window = window_share::find_window(&windows, &query).ok_or_else(|| {
+                    let titles: Vec<String> = windows.iter().map(|w| format!("0x{:x} {}", w.id, w.title)).collect();
+                    format!("Finestra non trovata: {}\nFinestre disponibili:\n{}", query, titles.join("\n"))
+                })?;
+                options.window = Some(window);
+            }
+            "--web" => options.web_addr = Some(value()?),
+            "--rtsp" => options.rtsp_addr = Some(value()?),
+            "--udp" => options.udp_enabled = true,
+            "--no-cursor" => options.show_cursor.store(false, Ordering::SeqCst),
+            "--click-highlight" => options.highlight_clicks.store(true, Ordering::SeqCst),
+            "--quic" => options.quic_addr = Some(value()?),
+            "--fps" => {
+                let fps: u32 = value()?.parse().map_err(|_| "Frame rate non valido".to_string())?;
+                options.target_fps.store(scheduler::clamp_fps(fps), Ordering::SeqCst);
+            }
+            "--encoder-threads" => {
+                options.encoder_threads = value()?.parse().map_err(|_| "Numero di thread non valido".to_string())?;
+            }
+            "--follow-mouse" => {
+                let size = value()?;
+                let (width, height) = size.split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .ok_or("Dimensione della finestra non valida".to_string())?;
+                options.follow_mouse = Some((width, height));
+            }
+            "--scale" => {
+                let scale = scaling::OutputScale::parse(&value()?).ok_or("Scala non valida".to_string())?;
+                *options.output_scale.write().unwrap() = scale;
+            }
+            "--udp-loss" => {
+                let percent: f32 = value()?.parse().map_err(|_| "Percentuale di perdita non valida".to_string())?;
+                options.udp_simulated_loss = (percent / 100.0).clamp(0.0, 1.0);
+            }
+            other => return Err(format!("Opzione non riconosciuta: {}", other)),
+        }
+    }
+
+    Ok((addr, options))
+}
+
+fn parse_receiver_args(args: &[String]) -> Result<(String, receiver::Transport), String> {
+    let mut args = args.iter();
+    let addr = args.next().ok_or("Indirizzo del caster mancante")?.clone();
+    let mut transport = receiver::Transport::Tcp;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--transport" => {
+                transport = match args.next().map(|v| v.as_str()) {
+                    Some("tcp") => receiver::Transport::Tcp,
+                    Some("udp") => receiver::Transport::Udp,
+                    Some("quic") => receiver::Transport::Quic,
+                    _ => return Err("Trasporto non valido".to_string()),
+                };
+            }
+            other => return Err(format!("Opzione non riconosciuta: {}", other)),
+        }
+    }
+
+    Ok((addr, transport))
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -28,6 +144,40 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
             eframe::run_native("Screencast App", options, Box::new(|_cc| Ok(Box::new(MyApp::default()))))?;
         }
+        "caster" => {
+            let (addr, options) = match parse_caster_args(&args[2..]) {
+                Ok(caster_args) => caster_args,
+                Err(e) => {
+                    eprintln!("{}\nUsage: {} {}", e, args[0], CASTER_USAGE);
+                    std::process::exit(1);
+                }
+            };
+            caster::start_caster(
+                &addr,
+                Arc::new(AtomicBool::new(false)),
+                Arc::new(AtomicBool::new(false)),
+                Arc::new(AtomicBool::new(false)),
+                Arc::new(AtomicBool::new(false)),
+                options,
+            ).await?;
+        }
+        "receiver" => {
+            let (addr, transport) = match parse_receiver_args(&args[2..]) {
+                Ok(receiver_args) => receiver_args,
+                Err(e) => {
+                    eprintln!("{}\nUsage: {} {}", e, args[0], RECEIVER_USAGE);
+                    std::process::exit(1);
+                }
+            };
+            receiver::receive_frame(
+                &addr,
+                transport,
+                Arc::new(AtomicBool::new(false)),
+                Arc::new(RwLock::new(receiver::SharedFrame::default())),
+                Arc::new(RwLock::new(receiver::ReceiverState::new())),
+                Arc::new(AtomicBool::new(false)),
+            ).await?;
+        }
         _ => {
             eprintln!("Usage: {} <caster|receiver|ui>", args[0]);
             std::process::exit(1);
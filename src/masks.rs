@@ -0,0 +1,264 @@
+use std::fs;
+use std::io;
+
+// Zone dello schermo nascoste prima della compressione (chat, notifiche, email...).
+// Le coordinate sono in pixel del display a cui la maschera appartiene e l'elenco viene
+// salvato in un file di testo nella cartella di lavoro, una maschera per riga. Insieme alla
+// maschera si salva la dimensione del display quando è stata disegnata: dopo un cambio di
+// risoluzione la si riscala su una copia, senza toccare quella salvata.
+const MASKS_FILE: &str = "privacy_masks.txt";
+
+const PIXEL_BLOCK: usize = 16;
+const BLUR_RADIUS: usize = 12;
+const BLUR_PASSES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MaskMode {
+    Fill,
+    #[default]
+    Pixelate,
+    Blur,
+}
+
+impl MaskMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MaskMode::Fill => "Riempimento",
+            MaskMode::Pixelate => "Pixel",
+            MaskMode::Blur => "Sfocatura",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            MaskMode::Fill => "fill",
+            MaskMode::Pixelate => "pixelate",
+            MaskMode::Blur => "blur",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fill" => Some(MaskMode::Fill),
+            "pixelate" => Some(MaskMode::Pixelate),
+            "blur" => Some(MaskMode::Blur),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrivacyMask {
+    pub display_index: usize,
+    pub min_x: usize,
+    pub min_y: usize,
+    pub max_x: usize,
+    pub max_y: usize,
+    pub mode: MaskMode,
+    // Dimensione del display (o del canvas) di riferimento, None per i file delle versioni precedenti
+    pub reference: Option<(usize, usize)>,
+}
+
+impl PrivacyMask {
+    // Maschera riportata sulla dimensione attuale della sorgente; gli estremi sono arrotondati
+    // verso l'esterno, così la zona nascosta non si restringe.
+    pub fn scaled(&self, size: (usize, usize)) -> PrivacyMask {
+        let Some((reference_width, reference_height)) = self.reference.filter(|&reference| reference != size) else {
+            return *self;
+        };
+        let floor = |value: usize, old: usize, new: usize| value * new / old.max(1);
+        let ceil = |value: usize, old: usize, new: usize| (value * new).div_ceil(old.max(1));
+        PrivacyMask {
+            min_x: floor(self.min_x, reference_width, size.0),
+            min_y: floor(self.min_y, reference_height, size.1),
+            max_x: ceil(self.max_x, reference_width, size.0),
+            max_y: ceil(self.max_y, reference_height, size.1),
+            ..*self
+        }
+    }
+}
+
+pub fn load_masks() -> Vec<PrivacyMask> {
+    let content = match fs::read_to_string(MASKS_FILE) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+    content.lines().filter_map(parse_mask).collect()
+}
+
+pub fn save_masks(masks: &[PrivacyMask]) -> io::Result<()> {
+    let content: String = masks.iter()
+        .map(|m| {
+            let line = format!("{} {} {} {} {} {}", m.display_index, m.min_x, m.min_y, m.max_x, m.max_y, m.mode.name());
+            match m.reference {
+                Some((width, height)) => format!("{} {} {}\n", line, width, height),
+                None => format!("{}\n", line),
+            }
+        })
+        .collect();
+    fs::write(MASKS_FILE, content)
+}
+
+fn parse_mask(line: &str) -> Option<PrivacyMask> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let reference = match fields.len() {
+        6 => None,
+        8 => Some((fields[6].parse().ok()?, fields[7].parse().ok()?)),
+        _ => return None,
+    };
+    Some(PrivacyMask {
+        display_index: fields[0].parse().ok()?,
+        min_x: fields[1].parse().ok()?,
+        min_y: fields[2].parse().ok()?,
+        max_x: fields[3].parse().ok()?,
+        max_y: fields[4].parse().ok()?,
+        mode: MaskMode::from_name(fields[5])?,
+        reference,
+    })
+}
+
+// Applica le maschere a un frame BGRA compatto che inizia in origin; maschere e origin sono
+// nelle coordinate della sorgente catturata (un display o il canvas con tutti i display).
+pub fn apply_masks(bgra: &mut [u8], width: usize, height: usize, origin: (usize, usize), masks: &[PrivacyMask]) {
+    for mask in masks {
+        // Intersezione con l'area catturata, in coordinate del frame
+        let min_x = mask.min_x.max(origin.0).saturating_sub(origin.0);
+        let min_y = mask.min_y.max(origin.1).saturating_sub(origin.1);
+        let max_x = mask.max_x.saturating_sub(origin.0).min(width);
+        let max_y = mask.max_y.saturating_sub(origin.1).min(height);
+        if min_x >= max_x || min_y >= max_y {
+            continue;
+        }
+        let region = Region { width, min_x, min_y, max_x, max_y };
+        match mask.mode {
+            MaskMode::Fill => fill(bgra, &region),
+            MaskMode::Pixelate => pixelate(bgra, &region),
+            MaskMode::Blur => {
+                for _ in 0..BLUR_PASSES {
+                    blur(bgra, &region);
+                }
+            }
+        }
+    }
+}
+
+struct Region {
+    width: usize,
+    min_x: usize,
+    min_y: usize,
+    max_x: usize,
+    max_y: usize,
+}
+
+impl Region {
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y * self.width + x) * 4
+    }
+}
+
+fn fill(bgra: &mut [u8], region: &Region) {
+    for y in region.min_y..region.max_y {
+        bgra[region.index(region.min_x, y)..region.index(region.max_x, y)].fill(0);
+    }
+}
+
+// Ogni blocco viene sostituito dal suo colore medio.
+fn pixelate(bgra: &mut [u8], region: &Region) {
+    for block_y in (region.min_y..region.max_y).step_by(PIXEL_BLOCK) {
+        for block_x in (region.min_x..region.max_x).step_by(PIXEL_BLOCK) {
+            let end_x = (block_x + PIXEL_BLOCK).min(region.max_x);
+            let end_y = (block_y + PIXEL_BLOCK).min(region.max_y);
+            let mut sum = [0u32; 3];
+            for y in block_y..end_y {
+                for x in block_x..end_x {
+                    let idx = region.index(x, y);
+                    for channel in 0..3 {
+                        sum[channel] += bgra[idx + channel] as u32;
+                    }
+                }
+            }
+            let count = ((end_x - block_x) * (end_y - block_y)) as u32;
+            let average = sum.map(|s| (s / count) as u8);
+            for y in block_y..end_y {
+                for x in block_x..end_x {
+                    let idx = region.index(x, y);
+                    bgra[idx..idx + 3].copy_from_slice(&average);
+                }
+            }
+        }
+    }
+}
+
+// Sfocatura a box separabile: ripetuta più volte approssima una gaussiana.
+fn blur(bgra: &mut [u8], region: &Region) {
+    let mut line = Vec::new();
+    for y in region.min_y..region.max_y {
+        line.clear();
+        line.extend((region.min_x..region.max_x).map(|x| pixel(bgra, region.index(x, y))));
+        for (offset, value) in box_filter(&line).into_iter().enumerate() {
+            let idx = region.index(region.min_x + offset, y);
+            bgra[idx..idx + 3].copy_from_slice(&value);
+        }
+    }
+    for x in region.min_x..region.max_x {
+        line.clear();
+        line.extend((region.min_y..region.max_y).map(|y| pixel(bgra, region.index(x, y))));
+        for (offset, value) in box_filter(&line).into_iter().enumerate() {
+            let idx = region.index(x, region.min_y + offset);
+            bgra[idx..idx + 3].copy_from_slice(&value);
+        }
+    }
+}
+
+fn pixel(bgra: &[u8], idx: usize) -> [u8; 3] {
+    [bgra[idx], bgra[idx + 1], bgra[idx + 2]]
+}
+
+// Media mobile di raggio BLUR_RADIUS, con i bordi della regione ripetuti.
+fn box_filter(line: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    let last = line.len() as isize - 1;
+    let at = |i: isize| line[i.clamp(0, last) as usize];
+    let radius = BLUR_RADIUS as isize;
+    let window = (2 * radius + 1) as u32;
+
+    let mut sum = [0u32; 3];
+    for i in -radius..=radius {
+        for (total, value) in sum.iter_mut().zip(at(i)) {
+            *total += value as u32;
+        }
+    }
+    let mut output = Vec::with_capacity(line.len());
+    for i in 0..line.len() as isize {
+        output.push(sum.map(|s| (s / window) as u8));
+        let (incoming, outgoing) = (at(i + radius + 1), at(i - radius));
+        for ((total, added), removed) in sum.iter_mut().zip(incoming).zip(outgoing) {
+            *total = *total + added as u32 - removed as u32;
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask(reference: Option<(usize, usize)>) -> PrivacyMask {
+        PrivacyMask { display_index: 0, min_x: 101, min_y: 51, max_x: 303, max_y: 153, mode: MaskMode::Fill, reference }
+    }
+
+    #[test]
+    fn scaling_does_not_accumulate() {
+        let saved = mask(Some((1920, 1080)));
+        assert_eq!(saved.scaled((1920, 1080)), saved);
+        let small = saved.scaled((1280, 720));
+        assert_eq!((small.min_x, small.min_y, small.max_x, small.max_y), (67, 34, 202, 102));
+        assert_eq!(mask(None).scaled((1280, 720)), mask(None));
+    }
+
+    #[test]
+    fn saved_lines_keep_the_reference() {
+        assert_eq!(parse_mask("0 101 51 303 153 fill 1920 1080"), Some(mask(Some((1920, 1080)))));
+        assert_eq!(parse_mask("0 101 51 303 153 fill"), Some(mask(None)));
+        assert_eq!(parse_mask("0 101 51 303 153 fill 1920"), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+use tokio::sync::broadcast;
+use crate::{convert, protocol, scaling};
+
+// Il thread di cattura consegna i frame grezzi a una catena di stadi su thread dedicati:
+// conversione BGRA -> RGB, compressione JPEG su più thread e invio sul canale broadcast.
+// Le code sono limitate: se la compressione non tiene il passo il frame viene scartato
+// alla cattura invece di accumulare ritardo.
+const QUEUE_DEPTH: usize = 2;
+const MAX_ENCODER_THREADS: usize = 4;
+
+// I buffer BGRA, RGB e JPEG tornano indietro allo stadio precedente dopo l'uso e ogni thread
+// di compressione riusa il proprio buffer ridimensionato. A regime l'unica allocazione per
+// frame è la copia del JPEG nel pacchetto condiviso: il canale broadcast tiene in vita gli
+// ultimi pacchetti, quindi il buffer di compressione non può essere passato ai client.
+// La copia è unica per frame ed è condivisa tra tutti i client e con chi si collega mentre
+// lo schermo è fermo.
+const SPARE_BUFFERS: usize = QUEUE_DEPTH + MAX_ENCODER_THREADS;
+
+pub struct RawFrame {
+    pub bgra: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub output_size: (usize, usize),
+}
+
+struct RgbFrame {
+    sequence: u64,
+    rgb: Vec<u8>,
+    width: usize,
+    height: usize,
+    output_size: (usize, usize),
+}
+
+pub struct EncodePipeline {
+    input: SyncSender<(u64, RawFrame)>,
+    free_raw: Receiver<Vec<u8>>,
+    spare: Option<Vec<u8>>,
+    next_sequence: u64,
+    last_packet: Arc<Mutex<Option<Arc<[u8]>>>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl EncodePipeline {
+    // encoder_threads = 0 usa un thread di compressione per core, fino a MAX_ENCODER_THREADS.
+    pub fn start(sender: Arc<broadcast::Sender<Arc<[u8]>>>, encoder_threads: usize) -> Self {
+        let encoder_threads = if encoder_threads == 0 {
+            thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(MAX_ENCODER_THREADS)
+        } else {
+            encoder_threads
+        };
+        println!("Compressione JPEG su {} thread", encoder_threads);
+
+        let (input, raw_rx) = sync_channel::<(u64, RawFrame)>(QUEUE_DEPTH);
+        let (free_raw_tx, free_raw) = sync_channel::<Vec<u8>>(SPARE_BUFFERS);
+        let (rgb_tx, rgb_rx) = sync_channel::<RgbFrame>(QUEUE_DEPTH);
+        let (free_rgb_tx, free_rgb_rx) = sync_channel::<Vec<u8>>(SPARE_BUFFERS);
+        let (jpeg_tx, jpeg_rx) = sync_channel::<(u64, (usize, usize), Vec<u8>)>(QUEUE_DEPTH * encoder_threads);
+        let (free_jpeg_tx, free_jpeg_rx) = sync_channel::<Vec<u8>>(SPARE_BUFFERS);
+        let last_packet = Arc::new(Mutex::new(None));
+        let mut threads = Vec::with_capacity(encoder_threads + 2);
+
+        threads.push(thread::spawn(move || convert_stage(raw_rx, free_raw_tx, rgb_tx, free_rgb_rx)));
+
+        let rgb_rx = Arc::new(Mutex::new(rgb_rx));
+        let free_jpeg_rx = Arc::new(Mutex::new(free_jpeg_rx));
+        for _ in 0..encoder_threads {
+            let rgb_rx = Arc::clone(&rgb_rx);
+            let free_rgb_tx = free_rgb_tx.clone();
+            let jpeg_tx = jpeg_tx.clone();
+            let free_jpeg_rx = Arc::clone(&free_jpeg_rx);
+            threads.push(thread::spawn(move || encode_stage(rgb_rx, free_rgb_tx, jpeg_tx, free_jpeg_rx)));
+        }
+        drop(jpeg_tx);
+
+        let last_packet_send = Arc::clone(&last_packet);
+        threads.push(thread::spawn(move || send_stage(jpeg_rx, free_jpeg_tx, sender, last_packet_send)));
+
+        Self {
+            input,
+            free_raw,
+            spare: None,
+            next_sequence: 0,
+            last_packet,
+            threads,
+        }
+    }
+
+    // Buffer vuoto per il prossimo frame grezzo, riciclato se disponibile.
+    pub fn take_buffer(&mut self) -> Vec<u8> {
+        let mut buffer = self.spare.take()
+            .or_else(|| self.free_raw.try_recv().ok())
+            .unwrap_or_default();
+        buffer.clear();
+        buffer
+    }
+
+    // Restituisce un buffer non inviato, ad esempio per un frame identico al precedente.
+    pub fn recycle(&mut self, buffer: Vec<u8>) {
+        self.spare = Some(buffer);
+    }
+
+    // Restituisce false se la pipeline è piena e il frame è stato scartato.
+    pub fn submit(&mut self, frame: RawFrame) -> bool {
+        match self.input.try_send((self.next_sequence, frame)) {
+            Ok(()) => {
+                self.next_sequence += 1;
+                true
+            }
+            Err(TrySendError::Full((_, frame))) => {
+                self.recycle(frame.bgra);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                eprintln!("Pipeline di compressione interrotta.");
+                false
+            }
+        }
+    }
+
+    // Ultimo pacchetto inviato, per chi si collega mentre lo schermo è fermo.
+    pub fn last_packet(&self) -> Option<Arc<[u8]>> {
+        self.last_packet.lock().unwrap().clone()
+    }
+
+    // Attende che i frame già in coda vengano inviati.
+    pub fn finish(self) {
+        drop(self.input);
+        for handle in self.threads {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn convert_stage(
+    raw_rx: Receiver<(u64, RawFrame)>,
+    free_raw_tx: SyncSender<Vec<u8>>,
+    rgb_tx: SyncSender<RgbFrame>,
+    free_rgb_rx: Receiver<Vec<u8>>,
+) {
+    for (sequence, frame) in raw_rx {
+        let mut rgb = free_rgb_rx.try_recv().unwrap_or_default();
+        convert::bgra_to_rgb(&frame.bgra, frame.width * 4, frame.width, frame.height, &mut rgb);
+        let _ = free_raw_tx.try_send(frame.bgra);
+        let rgb_frame = RgbFrame { sequence, rgb, width: frame.width, height: frame.height, output_size: frame.output_size };
+        if rgb_tx.send(rgb_frame).is_err() {
+            break;
+        }
+    }
+}
+
+fn encode_stage(
+    rgb_rx: Arc<Mutex<Receiver<RgbFrame>>>,
+    free_rgb_tx: SyncSender<Vec<u8>>,
+    jpeg_tx: SyncSender<(u64, (usize, usize), Vec<u8>)>,
+    free_jpeg_rx: Arc<Mutex<Receiver<Vec<u8>>>>,
+) {
+    let mut packet_capacity = 0;
+    let mut resizer = scaling::Resizer::default();
+    let mut scaled = Vec::new();
+    loop {
+        // Il lock serve solo a prelevare il frame: la compressione avviene in parallelo
+        let frame = match rgb_rx.lock().unwrap().recv() {
+            Ok(frame) => frame,
+            Err(_) => break,
+        };
+        // Il pacchetto parte dalla dimensione del frame precedente per evitare riallocazioni
+        let mut packet = free_jpeg_rx.lock().unwrap().try_recv().unwrap_or_default();
+        packet.reserve(packet_capacity);
+        // Il ridimensionamento è il passo più costoso dopo la compressione: si fa qui, in parallelo
+        let (output_width, output_height) = frame.output_size;
+        let result = if (output_width, output_height) == (frame.width, frame.height) {
+            compress_to_jpeg(&frame.rgb, frame.width, frame.height, &mut packet)
+        } else if resizer.resize(&frame.rgb, frame.width, frame.height, output_width, output_height, &mut scaled) {
+            compress_to_jpeg(&scaled, output_width, output_height, &mut packet)
+        } else {
+            eprintln!("Errore nel ridimensionamento del frame.");
+            let _ = free_rgb_tx.try_send(frame.rgb);
+            continue;
+        };
+        let _ = free_rgb_tx.try_send(frame.rgb);
+        match result {
+            Ok(()) => {
+                packet_capacity = packet.len() + packet.len() / 4;
+                if jpeg_tx.send((frame.sequence, frame.output_size, packet)).is_err() {
+                    break;
+                }
+            }
+            Err(e) => eprintln!("Errore nella compressione del frame: {}", e),
+        }
+    }
+}
+
+// I thread di compressione possono terminare fuori ordine: un frame completato dopo uno
+// più recente viene scartato. Quando la risoluzione in uscita cambia i receiver vengono
+// avvisati prima del frame.
+fn send_stage(
+    jpeg_rx: Receiver<(u64, (usize, usize), Vec<u8>)>,
+    free_jpeg_tx: SyncSender<Vec<u8>>,
+    sender: Arc<broadcast::Sender<Arc<[u8]>>>,
+    last_packet: Arc<Mutex<Option<Arc<[u8]>>>>,
+) {
+    let mut next_sequence = 0;
+    let mut last_size = None;
+    for (sequence, size, packet) in jpeg_rx {
+        if sequence < next_sequence {
+            let _ = free_jpeg_tx.try_send(packet);
+            continue;
+        }
+        next_sequence = sequence + 1;
+        if last_size.is_some_and(|last_size| last_size != size) {
+            println!("Nuova risoluzione in uscita: {}x{}", size.0, size.1);
+            let _ = sender.send(protocol::resolution(size.0 as u32, size.1 as u32).into());
+        }
+        last_size = Some(size);
+        let shared: Arc<[u8]> = Arc::from(packet.as_slice());
+        let _ = free_jpeg_tx.try_send(packet);
+        *last_packet.lock().unwrap() = Some(Arc::clone(&shared));
+        if let Err(e) = sender.send(shared) {
+            eprintln!("Errore nell'invio del frame: {}", e);
+        }
+    }
+}
+
+// Il JPEG viene scritto direttamente dopo l'intestazione del pacchetto, senza copie.
+pub fn compress_to_jpeg(rgb: &[u8], width: usize, height: usize, packet: &mut Vec<u8>) -> Result<(), jpeg_encoder::EncodingError> {
+    protocol::begin_frame(packet);
+    // Crominanza 4:2:0 e tabelle Huffman standard, come richiesto da RTP/JPEG (RFC 2435)
+    let mut encoder = Encoder::new(&mut *packet, 75);
+    encoder.set_sampling_factor(SamplingFactor::F_2_2);
+    encoder.encode(rgb, width as u16, height as u16, ColorType::Rgb)?;
+    protocol::finish_frame(packet);
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use std::fs;
+use std::io;
+use device_query::Keycode;
+use eframe::egui::{pos2, Rect};
+
+// Aree condivise salvate con un nome ("Slide", "IDE", "Browser") per passare dall'una
+// all'altra senza ridisegnarle. Il file contiene un preset per riga con i campi separati
+// da tabulazioni: nome, display, area ("x0 y0 x1 y1" o "intero") e tasto rapido ("-" se assente).
+const PRESETS_FILE: &str = "region_presets.txt";
+
+// Tasti assegnabili ai preset: F1 e F2 sono già usati per pausa e oscuramento
+pub const PRESET_HOTKEYS: [Keycode; 8] = [
+    Keycode::F5, Keycode::F6, Keycode::F7, Keycode::F8,
+    Keycode::F9, Keycode::F10, Keycode::F11, Keycode::F12,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionPreset {
+    pub name: String,
+    pub display_index: usize,
+    pub area: Option<Rect>,
+    pub hotkey: Option<Keycode>,
+}
+
+pub fn hotkey_label(hotkey: Option<Keycode>) -> String {
+    match hotkey {
+        Some(key) => format!("{:?}", key),
+        None => "Nessuno".to_string(),
+    }
+}
+
+fn parse_hotkey(name: &str) -> Option<Keycode> {
+    PRESET_HOTKEYS.iter().copied().find(|key| format!("{:?}", key) == name)
+}
+
+pub fn find_preset(presets: &[RegionPreset], name: &str) -> Option<usize> {
+    presets.iter().position(|preset| preset.name.eq_ignore_ascii_case(name))
+}
+
+pub fn load_presets() -> Vec<RegionPreset> {
+    let content = match fs::read_to_string(PRESETS_FILE) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+    content.lines().filter_map(parse_preset).collect()
+}
+
+pub fn save_presets(presets: &[RegionPreset]) -> io::Result<()> {
+    let content: String = presets.iter()
+        .map(|p| {
+            let area = match p.area {
+                Some(area) => format!("{} {} {} {}", area.min.x, area.min.y, area.max.x, area.max.y),
+                None => "intero".to_string(),
+            };
+            let hotkey = p.hotkey.map(|key| format!("{:?}", key)).unwrap_or_else(|| "-".to_string());
+            format!("{}\t{}\t{}\t{}\n", p.name, p.display_index, area, hotkey)
+        })
+        .collect();
+    fs::write(PRESETS_FILE, content)
+}
+
+fn parse_preset(line: &str) -> Option<RegionPreset> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 4 || fields[0].is_empty() {
+        return None;
+    }
+    let area = if fields[2] == "intero" {
+        None
+    } else {
+        let coords: Vec<f32> = fields[2].split_whitespace().map(|c| c.parse().ok()).collect::<Option<_>>()?;
+        if coords.len() != 4 {
+            return None;
+        }
+        Some(Rect::from_min_max(pos2(coords[0], coords[1]), pos2(coords[2], coords[3])))
+    };
+    Some(RegionPreset {
+        name: fields[0].to_string(),
+        display_index: fields[1].parse().ok()?,
+        area,
+        hotkey: parse_hotkey(fields[3]),
+    })
+}
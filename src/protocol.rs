@@ -0,0 +1,98 @@
+// Formato dei pacchetti che il caster pubblica sul canale broadcast e invia ai receiver TCP:
+// 4 byte big-endian con la lunghezza seguiti dal JPEG. Una lunghezza 0 indica la fine dello
+// stream, la lunghezza riservata KEEP_ALIVE (senza dati) segnala che lo schermo non è cambiato.
+// La lunghezza riservata RESOLUTION è seguita da larghezza e altezza (4 byte big-endian ciascuna)
+// e annuncia che i frame successivi hanno una nuova risoluzione. La lunghezza riservata
+// ANNOTATIONS è seguita da 4 byte con la lunghezza del contenuto e dai messaggi delle annotazioni
+// vettoriali (vedi annotations.rs).
+
+const KEEP_ALIVE: u32 = u32::MAX;
+const RESOLUTION: u32 = u32::MAX - 1;
+const ANNOTATIONS: u32 = u32::MAX - 2;
+
+pub enum Packet<'a> {
+    Frame(&'a [u8]),
+    KeepAlive,
+    Resolution(u32, u32),
+    Annotations(&'a [u8]),
+    EndOfStream,
+}
+
+// Per scrivere il JPEG direttamente nel pacchetto: si riservano i 4 byte della lunghezza
+// e li si compila a compressione terminata.
+pub fn begin_frame(packet: &mut Vec<u8>) {
+    packet.clear();
+    packet.extend_from_slice(&[0; 4]);
+}
+
+pub fn finish_frame(packet: &mut [u8]) {
+    let size = (packet.len() - 4) as u32;
+    packet[..4].copy_from_slice(&size.to_be_bytes());
+}
+
+pub fn keep_alive() -> Vec<u8> {
+    KEEP_ALIVE.to_be_bytes().to_vec()
+}
+
+pub fn end_of_stream() -> Vec<u8> {
+    vec![0, 0, 0, 0]
+}
+
+pub fn resolution(width: u32, height: u32) -> Vec<u8> {
+    let mut packet = RESOLUTION.to_be_bytes().to_vec();
+    packet.extend_from_slice(&width.to_be_bytes());
+    packet.extend_from_slice(&height.to_be_bytes());
+    packet
+}
+
+pub fn annotations(payload: &[u8]) -> Vec<u8> {
+    let mut packet = ANNOTATIONS.to_be_bytes().to_vec();
+    packet.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+// Pacchetti di controllo a lunghezza variabile: la lunghezza del contenuto è nei 4 byte successivi.
+pub fn has_payload_length(size_bytes: [u8; 4]) -> bool {
+    u32::from_be_bytes(size_bytes) == ANNOTATIONS
+}
+
+// Byte che seguono l'intestazione di un pacchetto di controllo, None per i frame.
+pub fn control_payload_len(size_bytes: [u8; 4]) -> Option<usize> {
+    match u32::from_be_bytes(size_bytes) {
+        KEEP_ALIVE => Some(0),
+        RESOLUTION => Some(8),
+        _ => None,
+    }
+}
+
+// I pacchetti malformati vengono trattati come fine dello stream.
+pub fn parse_packet(packet: &[u8]) -> Packet<'_> {
+    if packet.len() < 4 {
+        return Packet::EndOfStream;
+    }
+    let size_bytes = [packet[0], packet[1], packet[2], packet[3]];
+    match u32::from_be_bytes(size_bytes) {
+        KEEP_ALIVE => return Packet::KeepAlive,
+        RESOLUTION if packet.len() >= 12 => {
+            let width = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+            let height = u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]);
+            return Packet::Resolution(width, height);
+        }
+        RESOLUTION => return Packet::EndOfStream,
+        ANNOTATIONS if packet.len() >= 8 => {
+            let len = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]) as usize;
+            return match packet.get(8..8 + len) {
+                Some(payload) => Packet::Annotations(payload),
+                None => Packet::EndOfStream,
+            };
+        }
+        ANNOTATIONS => return Packet::EndOfStream,
+        _ => {}
+    }
+    let size = u32::from_be_bytes(size_bytes) as usize;
+    if size == 0 || packet.len() < 4 + size {
+        return Packet::EndOfStream;
+    }
+    Packet::Frame(&packet[4..4 + size])
+}
@@ -0,0 +1,316 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{ClientConfig, Connection, Endpoint, RecvStream, ServerConfig, TransportConfig};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use tokio::io::AsyncReadExt;
+use tokio::net::lookup_host;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{timeout, Duration};
+use crate::protocol;
+
+// Ogni frame (nello stesso formato del trasporto TCP) viaggia su uno stream unidirezionale
+// dedicato: un pacchetto perso rallenta solo il frame a cui appartiene, e i frame in eccesso
+// vengono saltati. Gli altri pacchetti (fine dello stream, risoluzione, keep-alive, annotazioni)
+// non si possono perdere né riordinare: viaggiano tutti sul primo stream aperto dal caster,
+// ciascuno preceduto dalla sua lunghezza (4 byte big-endian).
+const SERVER_NAME: &str = "screencast";
+const MAX_PACKET_SIZE: usize = 10_000_000;
+const MAX_FRAMES_IN_FLIGHT: usize = 8;
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+    config.keep_alive_interval(Some(Duration::from_secs(1)));
+    config.max_idle_timeout(Some(Duration::from_secs(10).try_into().unwrap()));
+    config.max_concurrent_uni_streams(64u32.into());
+    Arc::new(config)
+}
+
+pub async fn start_quic_server(
+    addr: &str,
+    sender: Arc<broadcast::Sender<Arc<[u8]>>>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let bind_addr = lookup_host(addr).await?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Indirizzo QUIC non valido"))?;
+
+    // Certificato autofirmato generato a ogni avvio: il traffico è cifrato, ma i receiver
+    // non possono verificare l'identità del caster.
+    let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()]).map_err(io::Error::other)?;
+    let cert = CertificateDer::from(certified.cert);
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    let mut server_config = ServerConfig::with_single_cert(vec![cert], key.into()).map_err(io::Error::other)?;
+    server_config.transport_config(transport_config());
+    server_config.migration(true);
+
+    let endpoint = Endpoint::server(server_config, bind_addr)?;
+    println!("Trasporto QUIC disponibile su {}", addr);
+
+    tokio::spawn(async move {
+        while !stop_signal.load(Ordering::SeqCst) && !terminate.load(Ordering::SeqCst) {
+            // Come per i listener TCP, dopo Stop la porta viene liberata senza attendere un nuovo client
+            let incoming = match timeout(STOP_CHECK_INTERVAL, endpoint.accept()).await {
+                Ok(Some(incoming)) => incoming,
+                Ok(None) => break,
+                Err(_) => continue,
+            };
+            let rx = sender.subscribe();
+            let stop_signal_client = Arc::clone(&stop_signal);
+            let terminate_client = Arc::clone(&terminate);
+            tokio::spawn(async move {
+                match incoming.await {
+                    Ok(connection) => {
+                        let peer = connection.remote_address();
+                        println!("Nuova connessione QUIC da: {}", peer);
+                        send_frames(connection, rx, stop_signal_client, terminate_client).await;
+                        println!("Connessione QUIC chiusa con {}", peer);
+                    }
+                    Err(e) => eprintln!("Errore nell'handshake QUIC: {}", e),
+                }
+            });
+        }
+        endpoint.close(0u32.into(), b"stop");
+        println!("Trasporto QUIC interrotto.");
+    });
+
+    Ok(())
+}
+
+async fn send_frames(
+    connection: Connection,
+    mut rx: broadcast::Receiver<Arc<[u8]>>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+) {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let mut control = match connection.open_uni().await {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Errore nell'apertura dello stream QUIC di controllo: {}", e);
+            return;
+        }
+    };
+
+    while !stop_signal.load(Ordering::SeqCst) && !terminate.load(Ordering::SeqCst) {
+        let packet = tokio::select! {
+            received = rx.recv() => match received {
+                Ok(packet) => packet,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    eprintln!("Avviso: connessione QUIC in ritardo, saltati {} frame", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = connection.closed() => break,
+        };
+
+        if !matches!(protocol::parse_packet(&packet), protocol::Packet::Frame(_)) {
+            let length = (packet.len() as u32).to_be_bytes();
+            if control.write_all(&length).await.is_err() || control.write_all(&packet).await.is_err() {
+                eprintln!("Errore nell'invio sullo stream QUIC di controllo");
+                break;
+            }
+            continue;
+        }
+
+        // Se il receiver non riesce a smaltire i frame si salta quello corrente invece di accodarlo
+        if in_flight.load(Ordering::SeqCst) >= MAX_FRAMES_IN_FLIGHT {
+            continue;
+        }
+        let mut stream = match connection.open_uni().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Errore nell'apertura dello stream QUIC: {}", e);
+                break;
+            }
+        };
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        let in_flight_stream = Arc::clone(&in_flight);
+        tokio::spawn(async move {
+            if stream.write_all(&packet).await.is_ok() {
+                let _ = stream.finish();
+            }
+            in_flight_stream.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+// Si connette al caster e restituisce i pacchetti ricevuti, nello stesso formato del trasporto TCP.
+pub async fn connect(addr: &str, stop_signal: Arc<AtomicBool>) -> io::Result<mpsc::Receiver<io::Result<Vec<u8>>>> {
+    let server_addr = lookup_host(addr).await?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Indirizzo QUIC non valido"))?;
+    let bind_addr: SocketAddr = if server_addr.is_ipv4() { "0.0.0.0:0".parse().unwrap() } else { "[::]:0".parse().unwrap() };
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let crypto = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+        .with_no_client_auth();
+    let mut client_config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto).map_err(io::Error::other)?));
+    client_config.transport_config(transport_config());
+
+    let mut endpoint = Endpoint::client(bind_addr)?;
+    endpoint.set_default_client_config(client_config);
+
+    let connecting = endpoint.connect(server_addr, SERVER_NAME).map_err(io::Error::other)?;
+    let connection = match timeout(Duration::from_secs(2), connecting).await {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(e)) => return Err(io::Error::other(e)),
+        Err(_) => {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Timeout di connessione al caster QUIC scaduto, controlla l'indirizzo IP inserito e riprova."));
+        }
+    };
+
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(async move {
+        match connection.accept_uni().await {
+            Ok(control) => {
+                tokio::spawn(read_control_stream(control, tx.clone()));
+            }
+            Err(e) => {
+                let _ = tx.send(Err(io::Error::new(io::ErrorKind::ConnectionAborted, e))).await;
+                return;
+            }
+        }
+        // Gli stream dei frame arrivano in parallelo: si scartano quelli completati dopo uno più recente
+        let last_delivered = Arc::new(AtomicU64::new(0));
+        while !stop_signal.load(Ordering::SeqCst) && !tx.is_closed() {
+            let mut stream = match connection.accept_uni().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    let _ = tx.send(Err(io::Error::new(io::ErrorKind::ConnectionAborted, e))).await;
+                    break;
+                }
+            };
+            let tx_stream = tx.clone();
+            let last_delivered_stream = Arc::clone(&last_delivered);
+            tokio::spawn(async move {
+                let order = stream.id().index() + 1;
+                if let Ok(packet) = stream.read_to_end(MAX_PACKET_SIZE).await {
+                    if last_delivered_stream.fetch_max(order, Ordering::SeqCst) < order {
+                        let _ = tx_stream.send(Ok(packet)).await;
+                    }
+                }
+            });
+        }
+        connection.close(0u32.into(), b"stop");
+        endpoint.wait_idle().await;
+    });
+
+    Ok(rx)
+}
+
+async fn read_control_stream(mut stream: RecvStream, tx: mpsc::Sender<io::Result<Vec<u8>>>) {
+    loop {
+        let packet = async {
+            let length = stream.read_u32().await? as usize;
+            if length > MAX_PACKET_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Pacchetto di controllo troppo grande"));
+            }
+            let mut packet = vec![0u8; length];
+            stream.read_exact(&mut packet).await.map_err(io::Error::other)?;
+            Ok(packet)
+        }.await;
+        // La chiusura dello stream viene segnalata dalla connessione, non serve inoltrarla
+        match packet {
+            Ok(packet) => {
+                if tx.send(Ok(packet)).await.is_err() {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+// I certificati del caster sono autofirmati, quindi la verifica viene saltata e si
+// controllano solo le firme dell'handshake.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn control_packets_are_never_skipped() {
+        let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let addr = format!("127.0.0.1:{}", port);
+        let (sender, _) = broadcast::channel(256);
+        let sender = Arc::new(sender);
+        let stop = Arc::new(AtomicBool::new(false));
+        start_quic_server(&addr, Arc::clone(&sender), Arc::clone(&stop), Arc::new(AtomicBool::new(false))).await.unwrap();
+        let mut packets = connect(&addr, Arc::clone(&stop)).await.unwrap();
+        // Il caster apre gli stream solo dopo aver accettato la connessione
+        while sender.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Molti frame in una volta superano MAX_FRAMES_IN_FLIGHT: possono essere saltati,
+        // i pacchetti di controllo in mezzo no, e arrivano nell'ordine di invio
+        for i in 0..100u32 {
+            let mut frame = Vec::new();
+            protocol::begin_frame(&mut frame);
+            frame.extend(std::iter::repeat_n(i as u8, 50_000));
+            protocol::finish_frame(&mut frame);
+            sender.send(frame.into()).unwrap();
+            if i % 10 == 0 {
+                sender.send(protocol::resolution(i, i).into()).unwrap();
+            }
+        }
+        sender.send(protocol::end_of_stream().into()).unwrap();
+
+        let mut resolutions = Vec::new();
+        loop {
+            let packet = timeout(Duration::from_secs(5), packets.recv()).await.unwrap().unwrap().unwrap();
+            match protocol::parse_packet(&packet) {
+                protocol::Packet::Resolution(width, _) => resolutions.push(width),
+                protocol::Packet::EndOfStream => break,
+                _ => {}
+            }
+        }
+        assert_eq!(resolutions, (0..100).step_by(10).collect::<Vec<u32>>());
+        stop.store(true, Ordering::SeqCst);
+    }
+}
@@ -7,9 +7,19 @@ use std::fs;
 use std::path::Path;
 use chrono::Local;
 use std::io::{self};
-use image::RgbaImage;
+use image::{imageops, Rgba, RgbaImage};
 use std::process::Command;
 use std::time::{ Instant};
+use tokio::sync::mpsc;
+use std::collections::BTreeMap;
+use eframe::egui::{vec2, Pos2, Rect};
+use crate::{annotations, protocol, quic, udp};
+use crate::annotations::{Annotation, AnnotationRenderer};
+
+// Elenco dei frame registrati con la loro durata, letto da ffmpeg
+const FRAME_LIST: &str = "frames.txt";
+// Per quanto resta visibile l'avviso di cambio risoluzione del caster
+const RESOLUTION_NOTICE: Duration = Duration::from_secs(5);
 
 pub struct ReceiverState {
     pub recording: bool,
@@ -17,13 +27,23 @@ pub struct ReceiverState {
     pub(crate) output_dir: String,
     frame_width: Option<u32>,
     frame_height: Option<u32>,
-    last_frame_time: Option<Instant>,
     start_time: Option<Instant>,
     paused_duration: Duration,
     pause_start_time: Option<Instant>,
+    // Istante di ogni frame salvato dall'inizio della registrazione, pause escluse: con lo schermo
+    // fermo il caster invia solo keep-alive, quindi i frame non arrivano a intervalli regolari
+    frame_times: Vec<Duration>,
     pub framerate: f64,
     pub is_paused: bool,
     last_frame_received: Option<Instant>,
+    // Ultima risoluzione annunciata dal caster e istante dell'annuncio
+    source_resolution: Option<((u32, u32), Instant)>,
+    // Annotazioni vettoriali del caster, in coordinate normalizzate rispetto al frame
+    pub annotations: BTreeMap<u32, Annotation>,
+    pub show_annotations: bool,
+    pub record_annotations: bool,
+    annotation_renderer: AnnotationRenderer,
+    small_frame_logged: bool,
 }
 
 impl ReceiverState {
@@ -34,23 +54,37 @@ impl ReceiverState {
             output_dir: String::new(),
             frame_width: None,
             frame_height: None,
-            last_frame_time: None,
             start_time: None,
             paused_duration: Duration::new(0, 0),
             pause_start_time: None,
+            frame_times: Vec::new(),
             framerate: 30.0,
             is_paused: false,
             last_frame_received: None,
+            source_resolution: None,
+            annotations: BTreeMap::new(),
+            show_annotations: true,
+            record_annotations: true,
+            annotation_renderer: AnnotationRenderer::new(),
+            small_frame_logged: false,
         }
     }
+    // Nuova risoluzione del caster, solo per qualche secondo dopo il cambio.
+    pub fn resolution_notice(&self) -> Option<(u32, u32)> {
+        self.source_resolution
+            .filter(|(_, changed)| changed.elapsed() < RESOLUTION_NOTICE)
+            .map(|(size, _)| size)
+    }
+
     pub(crate) fn reset_parameter(&mut self){
         self.recording = false;
         self.frame_count = 0;
         self.frame_width = None;
         self.frame_height = None;
-        self.last_frame_time = None;
         self.paused_duration = Duration::new(0, 0);
         self.pause_start_time=None;
+        self.frame_times.clear();
+        self.source_resolution = None;
     }
 
     pub fn start_recording(&mut self) -> io::Result<()> {
@@ -66,8 +100,9 @@ impl ReceiverState {
         self.recording = true;
         self.frame_count = 0;
         self.start_time = Some(Instant::now());
-        self.last_frame_time = Some(Instant::now());
         self.paused_duration = Duration::new(0, 0);
+        self.frame_times.clear();
+        self.small_frame_logged = false;
         println!("Started recording in: {}", self.output_dir);
         Ok(())
     }
@@ -78,37 +113,61 @@ impl ReceiverState {
         }
 
         let (width, height) = img.dimensions();
-        if width < 10 || height < 10 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Frame dimensions too small (minimum 10x10 pixels required)",
-            ));
+        // Un ritaglio molto piccolo non può dare le dimensioni alla registrazione: si salta il frame
+        // senza interrompere la ricezione. A registrazione avviata viene adattato come gli altri.
+        if self.frame_width.is_none() && (width < 10 || height < 10) {
+            if !self.small_frame_logged {
+                eprintln!("Frame di {}x{} troppo piccolo per la registrazione (minimo 10x10), saltato", width, height);
+                self.small_frame_logged = true;
+            }
+            return Ok(());
         }
 
+        // Le annotazioni vettoriali vengono disegnate nel frame registrato alla sua risoluzione
+        let annotated;
+        let img = if self.record_annotations && !self.annotations.is_empty() {
+            let target = Rect::from_min_size(Pos2::ZERO, vec2(width as f32, height as f32));
+            let shapes: Vec<Annotation> = self.annotations.values().map(|a| a.denormalized(target)).collect();
+            let mut copy = img.clone();
+            self.annotation_renderer.draw_rgba(&mut copy, width as usize, height as usize, &shapes);
+            annotated = copy;
+            &annotated
+        } else {
+            img
+        };
+
         if self.frame_width.is_none() {
             self.frame_width = Some(width);
             self.frame_height = Some(height);
         }
 
-        if Some(width) != self.frame_width || Some(height) != self.frame_height {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Frame dimensions changed during recording",
-            ));
-        }
+        // Il caster può cambiare area o display durante la trasmissione: il frame viene adattato
+        // alle dimensioni iniziali della registrazione, con bande nere
+        let fitted;
+        let img = match (self.frame_width, self.frame_height) {
+            (Some(frame_width), Some(frame_height)) if (width, height) != (frame_width, frame_height) => {
+                fitted = fit_frame(img, frame_width, frame_height);
+                &fitted
+            }
+            _ => img,
+        };
 
         let frame_path = Path::new(&self.output_dir)
             .join("frames")
             .join(format!("frame_{:06}.png", self.frame_count));
 
         img.save(&frame_path)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
 
         self.frame_count += 1;
-        self.last_frame_time = Some(Instant::now());
+        self.frame_times.push(self.recording_time());
         Ok(())
     }
 
+    fn recording_time(&self) -> Duration {
+        self.start_time.map_or(Duration::ZERO, |start| start.elapsed().saturating_sub(self.paused_duration))
+    }
+
     pub fn stop_recording(&mut self) -> io::Result<()> {
         if !self.recording {
             return Ok(());
@@ -117,15 +176,16 @@ impl ReceiverState {
 
         if self.frame_count == 0 {
             self.reset_parameter();
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::other(
                 "No frames were recorded",
             ));
         }
 
-        let duration = self.last_frame_time.unwrap().duration_since(self.start_time.unwrap())- self.paused_duration;
+        // Ogni frame dura fino al successivo: i tratti a schermo fermo mantengono la loro durata
+        let duration = self.recording_time();
         self.framerate = self.frame_count as f64 / duration.as_secs_f64();
-        println!("Framerate effettivo: {:.2} fps", self.framerate );
+        println!("Framerate medio: {:.2} fps", self.framerate );
+        fs::write(Path::new(&self.output_dir).join(FRAME_LIST), frame_list(&self.frame_times, duration))?;
 
         let metadata = format!(
             "frames: {}\nfps: {:.2}\nwidth: {}\nheight: {}\nstart_time: {}\n",
@@ -139,7 +199,6 @@ impl ReceiverState {
         fs::write(Path::new(&self.output_dir).join("metadata.txt"), metadata)?;
 
         let output_dir = self.output_dir.clone();
-        let framerate = self.framerate;
         let frame_width = self.frame_width;
         let frame_height = self.frame_height;
 
@@ -147,7 +206,7 @@ impl ReceiverState {
         self.reset_parameter();
 
         tokio::spawn(async move {
-            let conversion_result = convert_to_mp4(&output_dir, framerate, frame_width, frame_height);
+            let conversion_result = convert_to_mp4(&output_dir, frame_width, frame_height);
 
             if conversion_result.is_ok() {
                 let _ = delete_frames(&output_dir);
@@ -158,11 +217,36 @@ impl ReceiverState {
     }
 
 }
+fn fit_frame(img: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let scale = (width as f32 / img.width() as f32).min(height as f32 / img.height() as f32);
+    let scaled_width = ((img.width() as f32 * scale).round() as u32).clamp(1, width);
+    let scaled_height = ((img.height() as f32 * scale).round() as u32).clamp(1, height);
+    let scaled = imageops::resize(img, scaled_width, scaled_height, imageops::FilterType::Triangle);
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    imageops::overlay(&mut canvas, &scaled, ((width - scaled_width) / 2) as i64, ((height - scaled_height) / 2) as i64);
+    canvas
+}
+
+// Elenco dei frame per il demuxer concat di ffmpeg, con la durata di ciascuno. L'ultimo file va
+// ripetuto senza durata, altrimenti ffmpeg ignora la durata dell'ultimo frame.
+fn frame_list(frame_times: &[Duration], end: Duration) -> String {
+    let mut list = String::new();
+    for (index, time) in frame_times.iter().enumerate() {
+        let next = frame_times.get(index + 1).copied().unwrap_or(end).max(*time);
+        list.push_str(&format!("file 'frames/frame_{:06}.png'\nduration {:.6}\n", index, (next - *time).as_secs_f64()));
+    }
+    if let Some(last) = frame_times.len().checked_sub(1) {
+        list.push_str(&format!("file 'frames/frame_{:06}.png'\n", last));
+    }
+    list
+}
+
 fn delete_frames(output_dir: &str) -> io::Result<()> {
     fs::remove_dir_all(Path::new(output_dir).join("frames"))?;
+    fs::remove_file(Path::new(output_dir).join(FRAME_LIST))?;
     Ok(())
 }
-fn convert_to_mp4(output_dir: &str, framerate: f64, width: Option<u32>, height: Option<u32>) -> io::Result<()> {
+fn convert_to_mp4(output_dir: &str, width: Option<u32>, height: Option<u32>) -> io::Result<()> {
     if width.is_none() || height.is_none() {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -171,9 +255,11 @@ fn convert_to_mp4(output_dir: &str, framerate: f64, width: Option<u32>, height:
     }
 
     let output = Command::new("ffmpeg")
-        .args(&[
-            "-framerate", &format!("{:.2}", framerate),
-            "-i", &format!("{}/frames/frame_%06d.png", output_dir),
+        .args([
+            "-f", "concat",
+            "-i", &format!("{}/{}", output_dir, FRAME_LIST),
+            // Frame a durata variabile, come sono arrivati dal caster
+            "-vsync", "vfr",
             "-vf", "scale=ceil(iw/2)*2:ceil(ih/2)*2",
             "-c:v", "libx264",
             "-pix_fmt", "yuv420p",
@@ -185,8 +271,7 @@ fn convert_to_mp4(output_dir: &str, framerate: f64, width: Option<u32>, height:
         .output()?;
 
     if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
+        return Err(io::Error::other(
             String::from_utf8_lossy(&output.stderr)
         ));
     }
@@ -195,6 +280,7 @@ fn convert_to_mp4(output_dir: &str, framerate: f64, width: Option<u32>, height:
 }
 
 
+#[derive(Default)]
 pub struct SharedFrame {
     pub buffer: Vec<u8>,
     pub width: usize,
@@ -202,25 +288,28 @@ pub struct SharedFrame {
     pub new_frame: bool,
 }
 
-impl Default for SharedFrame {
-    fn default() -> Self {
-        Self {
-            buffer: Vec::new(),
-            width: 0,
-            height: 0,
-            new_frame: false,
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Transport {
+    #[default]
+    Tcp,
+    Udp,
+    Quic,
+}
+
+impl Transport {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Transport::Tcp => "TCP",
+            Transport::Udp => "UDP (FEC)",
+            Transport::Quic => "QUIC",
         }
     }
 }
 
-pub async fn receive_frame(
-    addr: &str,
-    stop_signal: Arc<AtomicBool>,
-    shared_frame: Arc<RwLock<SharedFrame>>,
-    receiver_state: Arc<RwLock<ReceiverState>>,
-    connected_to_caster: Arc<AtomicBool>
-) -> io::Result<()> {
-
+// Apre la connessione TCP e legge i pacchetti in un task separato, così un timeout sul
+// canale non interrompe mai la lettura a metà di un frame.
+async fn connect_tcp(addr: &str) -> io::Result<mpsc::Receiver<io::Result<Vec<u8>>>> {
     let read_timeout = Duration::from_secs(2);
     let mut stream;
     match timeout(read_timeout, TcpStream::connect(addr)).await {
@@ -235,69 +324,172 @@ pub async fn receive_frame(
         }
     };
 
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(async move {
+        loop {
+            let packet = read_tcp_packet(&mut stream).await;
+            let failed = packet.is_err();
+            if tx.send(packet).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+async fn read_tcp_packet(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut size_buf = [0u8; 4];
+    stream.read_exact(&mut size_buf).await?;
+    if protocol::has_payload_length(size_buf) {
+        let mut len_buf = [0u8; 4];
+        stream.read_exact(&mut len_buf).await?;
+        let payload_len = u32::from_be_bytes(len_buf) as usize;
+        if payload_len > 10_000_000 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Pacchetto di controllo troppo grande"));
+        }
+        let mut packet = vec![0u8; 8 + payload_len];
+        packet[..4].copy_from_slice(&size_buf);
+        packet[4..8].copy_from_slice(&len_buf);
+        stream.read_exact(&mut packet[8..]).await?;
+        return Ok(packet);
+    }
+    if let Some(payload_len) = protocol::control_payload_len(size_buf) {
+        let mut packet = vec![0u8; 4 + payload_len];
+        packet[..4].copy_from_slice(&size_buf);
+        stream.read_exact(&mut packet[4..]).await?;
+        return Ok(packet);
+    }
+    let frame_size = u32::from_be_bytes(size_buf) as usize;
+
+    if frame_size > 10_000_000 {
+        eprintln!("Frame troppo grande: {} byte", frame_size);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame troppo grande"));
+    }
+
+    let mut packet = vec![0u8; 4 + frame_size];
+    packet[..4].copy_from_slice(&size_buf);
+    stream.read_exact(&mut packet[4..]).await?;
+    Ok(packet)
+}
+
+fn decode_frame(
+    jpeg: &[u8],
+    shared_frame: &Arc<RwLock<SharedFrame>>,
+    receiver_state: &Arc<RwLock<ReceiverState>>,
+) -> io::Result<()> {
+    let img = ImageReader::new(std::io::Cursor::new(jpeg))
+        .with_guessed_format()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Errore nel formato dell'immagine: {}", e)))?
+        .decode()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Errore durante la decodifica dell'immagine: {}", e)))?;
+
+    let img = img.to_rgba8();
+    let (width, height) = img.dimensions();
+
+    if let Ok(mut shared) = shared_frame.write() {
+        shared.buffer = img.to_vec();
+        shared.width = width as usize;
+        shared.height = height as usize;
+        shared.new_frame = true;
+    }
+
+    if let Ok(mut receiver_state)=receiver_state.write(){
+        // Un errore nel salvataggio non deve chiudere la connessione con il caster
+        if let Err(e) = receiver_state.save_frame(&img) {
+            eprintln!("Errore nel salvataggio del frame: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn stop_recording_on_disconnect(receiver_state: &Arc<RwLock<ReceiverState>>) {
+    if let Ok(mut receiver_state) = receiver_state.write() {
+        if receiver_state.recording {
+            let _ = receiver_state.stop_recording();
+        }
+    }
+}
+
+pub async fn receive_frame(
+    addr: &str,
+    transport: Transport,
+    stop_signal: Arc<AtomicBool>,
+    shared_frame: Arc<RwLock<SharedFrame>>,
+    receiver_state: Arc<RwLock<ReceiverState>>,
+    connected_to_caster: Arc<AtomicBool>
+) -> io::Result<()> {
+
+    let mut packets = match transport {
+        Transport::Tcp => connect_tcp(addr).await?,
+        Transport::Udp => udp::connect(addr, Arc::clone(&stop_signal)).await?,
+        Transport::Quic => quic::connect(addr, Arc::clone(&stop_signal)).await?,
+    };
+
     connected_to_caster.store(true, Ordering::SeqCst);
+    // Le annotazioni di una trasmissione precedente non valgono per questa
+    if let Ok(mut state) = receiver_state.write() {
+        state.annotations.clear();
+    }
     let read_timeout = Duration::from_secs(2);
     let mut no_frame_received = false;
 
     while !stop_signal.load(Ordering::SeqCst) {
-        let mut size_buf = [0u8; 4];
-
-        match timeout(read_timeout, stream.read_exact(&mut size_buf)).await {
-            Ok(Ok(_)) => {
-                let frame_size = u32::from_be_bytes(size_buf) as usize;
-                println!("Ricevuto frame di dimensione: {} byte", frame_size);
+        match timeout(read_timeout, packets.recv()).await {
+            Ok(Some(Ok(packet))) => {
+                let jpeg = match protocol::parse_packet(&packet) {
+                    protocol::Packet::Frame(jpeg) => Some(jpeg),
+                    protocol::Packet::KeepAlive => None,
+                    protocol::Packet::Resolution(width, height) => {
+                        println!("Il caster ha cambiato risoluzione: {}x{}", width, height);
+                        if let Ok(mut state) = receiver_state.write() {
+                            state.source_resolution = Some(((width, height), Instant::now()));
+                        }
+                        None
+                    }
+                    protocol::Packet::Annotations(payload) => {
+                        match annotations::decode_messages(payload) {
+                            Some(messages) => {
+                                if let Ok(mut state) = receiver_state.write() {
+                                    annotations::apply_messages(&mut state.annotations, messages);
+                                }
+                            }
+                            None => eprintln!("Messaggio delle annotazioni non valido, ignorato"),
+                        }
+                        None
+                    }
+                    protocol::Packet::EndOfStream => {
+                        stop_recording_on_disconnect(&receiver_state);
+                        connected_to_caster.store(false, Ordering::SeqCst);
+                        return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "Il caster ha chiuso la trasmissione."));
+                    }
+                };
 
+                // Anche il keep-alive conferma che il caster è attivo: lo schermo è solo fermo
                 if let Ok(mut state) = receiver_state.write() {
                     state.is_paused = false;
                     state.last_frame_received = Some(Instant::now());
                 }
 
-                if frame_size > 10_000_000 {
-                    eprintln!("Frame troppo grande: {} byte", frame_size);
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame troppo grande"));
-                }
-
-                let mut buffer = vec![0u8; frame_size];
-                stream.read_exact(&mut buffer).await?;
-
-                let img = ImageReader::new(std::io::Cursor::new(buffer))
-                    .with_guessed_format()
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Errore nel formato dell'immagine: {}", e)))?
-                    .decode()
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Errore durante la decodifica dell'immagine: {}", e)))?;
-
-                let img = img.to_rgba8();
-                let (width, height) = img.dimensions();
-
-                if let Ok(mut shared) = shared_frame.write() {
-                    shared.buffer = img.to_vec();
-                    shared.width = width as usize;
-                    shared.height = height as usize;
-                    shared.new_frame = true;
-                }
-
-                if let Ok(mut receiver_state)=receiver_state.write(){
-                    receiver_state.save_frame(&img)?;
+                if let Some(jpeg) = jpeg {
+                    println!("Ricevuto frame di dimensione: {} byte", jpeg.len());
+                    decode_frame(jpeg, &shared_frame, &receiver_state)?;
                 }
             }
 
-            Ok(Err(e)) => {
-                eprintln!("Errore durante la lettura della dimensione del frame: {}", e);
-
-                // Chiudi la connessione solo se è un errore irreversibile
-                if e.kind() == io::ErrorKind::UnexpectedEof || e.raw_os_error() == Some(10054) {
-                    if let Ok(mut receiver_state) = receiver_state.write() {
-                        if receiver_state.recording {
-                            let _ = receiver_state.stop_recording();
-                        }
-                    }
-                    connected_to_caster.store(false, Ordering::SeqCst);
-                    return Err(io::Error::new(e.kind(), "Connessione con il caster interrotta"));
+            Ok(Some(Err(e))) => {
+                eprintln!("Errore durante la ricezione del frame: {}", e);
+                stop_recording_on_disconnect(&receiver_state);
+                connected_to_caster.store(false, Ordering::SeqCst);
+                if e.kind() == io::ErrorKind::InvalidData {
+                    return Err(e);
                 }
+                return Err(io::Error::new(e.kind(), "Connessione con il caster interrotta"));
+            }
 
-                // Riprova la connessione invece di terminare
-                sleep(Duration::from_secs(1)).await;
-                continue;
+            Ok(None) => {
+                stop_recording_on_disconnect(&receiver_state);
+                connected_to_caster.store(false, Ordering::SeqCst);
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connessione con il caster interrotta"));
             }
 
             Err(_) => {
@@ -348,4 +540,34 @@ pub async fn receive_frame(
 
     println!("Receiver fermato.");
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_stretches_keep_their_duration() {
+        // Tre frame ravvicinati, poi lo schermo resta fermo per due secondi prima dell'ultimo
+        let times = [0, 40, 80, 2080].map(Duration::from_millis);
+        let list = frame_list(&times, Duration::from_millis(2120));
+        assert_eq!(list, "file 'frames/frame_000000.png'\nduration 0.040000\n\
+            file 'frames/frame_000001.png'\nduration 0.040000\n\
+            file 'frames/frame_000002.png'\nduration 2.000000\n\
+            file 'frames/frame_000003.png'\nduration 0.040000\n\
+            file 'frames/frame_000003.png'\n");
+        assert_eq!(frame_list(&[], Duration::from_secs(1)), "");
+    }
+
+    #[test]
+    fn resolution_notice_expires() {
+        let mut state = ReceiverState::new();
+        assert_eq!(state.resolution_notice(), None);
+        state.source_resolution = Some(((1280, 720), Instant::now()));
+        assert_eq!(state.resolution_notice(), Some((1280, 720)));
+        if let Some(changed) = Instant::now().checked_sub(RESOLUTION_NOTICE) {
+            state.source_resolution = Some(((1280, 720), changed));
+            assert_eq!(state.resolution_notice(), None);
+        }
+    }
+}
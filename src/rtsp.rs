@@ -0,0 +1,618 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use crate::{pipeline, protocol, scaling};
+use crate::scaling::OutputScale;
+
+// Payload type statico assegnato a JPEG (RFC 3551) e dimensione massima dei pacchetti RTP.
+const RTP_JPEG_PAYLOAD_TYPE: u8 = 26;
+const RTP_MAX_PACKET: usize = 1400;
+const RTP_CLOCK_RATE: f64 = 90_000.0;
+const MAX_REQUEST_HEAD: usize = 8192;
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+// RTP/JPEG trasmette larghezza e altezza in blocchi di 8 pixel su un byte: i frame più grandi
+// vengono ridotti prima dell'invio
+const RTP_JPEG_MAX_SIZE: usize = 2040;
+
+struct RtspRequest {
+    method: String,
+    url: String,
+    headers: HashMap<String, String>,
+}
+
+enum RtpSink {
+    Udp { socket: UdpSocket, destination: SocketAddr },
+    Interleaved { writer: Arc<Mutex<OwnedWriteHalf>>, channel: u8 },
+}
+
+impl RtpSink {
+    async fn send(&self, packet: &[u8]) -> io::Result<()> {
+        match self {
+            RtpSink::Udp { socket, destination } => {
+                socket.send_to(packet, destination).await?;
+            }
+            RtpSink::Interleaved { writer, channel } => {
+                let mut framed = Vec::with_capacity(4 + packet.len());
+                framed.push(b'$');
+                framed.push(*channel);
+                framed.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+                framed.extend_from_slice(packet);
+                writer.lock().await.write_all(&framed).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct RtspSession {
+    id: String,
+    ssrc: u32,
+    sink: Arc<RtpSink>,
+    streaming: Option<JoinHandle<()>>,
+}
+
+impl Drop for RtspSession {
+    fn drop(&mut self) {
+        if let Some(handle) = self.streaming.take() {
+            handle.abort();
+        }
+    }
+}
+
+// Frame JPEG baseline scomposto nei campi richiesti da RTP/JPEG (RFC 2435).
+struct JpegFrame<'a> {
+    jpeg_type: u8,
+    width: u16,
+    height: u16,
+    quant_tables: Vec<u8>,
+    scan: &'a [u8],
+}
+
+pub async fn start_rtsp_server(
+    addr: &str,
+    sender: Arc<broadcast::Sender<Arc<[u8]>>>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Server RTSP disponibile su rtsp://{}/", addr);
+    let downscaler = Arc::new(Downscaler::default());
+
+    tokio::spawn(async move {
+        while !stop_signal.load(Ordering::SeqCst) && !terminate.load(Ordering::SeqCst) {
+            // Il flag di stop si ricontrolla anche senza nuovi client, così dopo Stop la porta viene liberata subito
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = sleep(STOP_CHECK_INTERVAL) => continue,
+            };
+            if let Ok((socket, peer)) = accepted {
+                println!("Nuovo client RTSP da: {}", peer);
+                let sender_client = Arc::clone(&sender);
+                let stop_signal_client = Arc::clone(&stop_signal);
+                let terminate_client = Arc::clone(&terminate);
+                let downscaler_client = Arc::clone(&downscaler);
+                tokio::spawn(async move {
+                    if let Err(e) = handle_client(socket, peer, sender_client, downscaler_client, stop_signal_client, terminate_client).await {
+                        eprintln!("Errore nella sessione RTSP con {}: {}", peer, e);
+                    }
+                    println!("Client RTSP disconnesso: {}", peer);
+                });
+            }
+        }
+        println!("Server RTSP interrotto.");
+    });
+
+    Ok(())
+}
+
+async fn handle_client(
+    socket: TcpStream,
+    peer: SocketAddr,
+    sender: Arc<broadcast::Sender<Arc<[u8]>>>,
+    downscaler: Arc<Downscaler>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let local_ip = socket.local_addr()?.ip();
+    let (reader, writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    let writer = Arc::new(Mutex::new(writer));
+    let mut session: Option<RtspSession> = None;
+
+    while !stop_signal.load(Ordering::SeqCst) && !terminate.load(Ordering::SeqCst) {
+        let request = match read_request(&mut reader).await? {
+            Some(request) => request,
+            None => break,
+        };
+        let cseq = request.headers.get("cseq").cloned().unwrap_or_else(|| "0".to_string());
+        let session_id = session.as_ref().map(|s| s.id.clone());
+
+        let (status, headers, body) = match request.method.as_str() {
+            "OPTIONS" => (
+                "200 OK",
+                vec![("Public".to_string(), "OPTIONS, DESCRIBE, SETUP, PLAY, PAUSE, TEARDOWN, GET_PARAMETER".to_string())],
+                String::new(),
+            ),
+            "DESCRIBE" => {
+                let sdp = format!(
+                    "v=0\r\no=- {} 1 IN IP4 {}\r\ns=Screencast\r\nc=IN IP4 0.0.0.0\r\nt=0 0\r\nm=video 0 RTP/AVP {}\r\na=rtpmap:{} JPEG/90000\r\na=control:track0\r\n",
+                    unix_nanos(), local_ip, RTP_JPEG_PAYLOAD_TYPE, RTP_JPEG_PAYLOAD_TYPE
+                );
+                let base = if request.url.ends_with('/') { request.url.clone() } else { format!("{}/", request.url) };
+                (
+                    "200 OK",
+                    vec![
+                        ("Content-Base".to_string(), base),
+                        ("Content-Type".to_string(), "application/sdp".to_string()),
+                    ],
+                    sdp,
+                )
+            }
+            "SETUP" => {
+                let transport = request.headers.get("transport").cloned().unwrap_or_default();
+                let ssrc = unix_nanos() as u32;
+                match setup_transport(&transport, peer, Arc::clone(&writer), ssrc).await {
+                    Ok((sink, reply_transport)) => {
+                        let id = session_id.unwrap_or_else(|| format!("{:08X}", unix_nanos() as u32));
+                        session = Some(RtspSession { id: id.clone(), ssrc, sink: Arc::new(sink), streaming: None });
+                        (
+                            "200 OK",
+                            vec![
+                                ("Transport".to_string(), reply_transport),
+                                ("Session".to_string(), format!("{};timeout=60", id)),
+                            ],
+                            String::new(),
+                        )
+                    }
+                    Err(e) => {
+                        eprintln!("Trasporto RTSP non supportato ({}): {}", transport, e);
+                        ("461 Unsupported Transport", Vec::new(), String::new())
+                    }
+                }
+            }
+            "PLAY" => match session.as_mut() {
+                Some(current) => {
+                    if current.streaming.is_none() {
+                        let sink = Arc::clone(&current.sink);
+                        let rx = sender.subscribe();
+                        let ssrc = current.ssrc;
+                        let downscaler = Arc::clone(&downscaler);
+                        let stop_signal_stream = Arc::clone(&stop_signal);
+                        let terminate_stream = Arc::clone(&terminate);
+                        current.streaming = Some(tokio::spawn(async move {
+                            if let Err(e) = stream_rtp(sink, rx, ssrc, &downscaler, stop_signal_stream, terminate_stream).await {
+                                eprintln!("Errore nell'invio RTP a {}: {}", peer, e);
+                            }
+                        }));
+                    }
+                    (
+                        "200 OK",
+                        vec![
+                            ("Session".to_string(), current.id.clone()),
+                            ("Range".to_string(), "npt=0.000-".to_string()),
+                        ],
+                        String::new(),
+                    )
+                }
+                None => ("455 Method Not Valid in This State", Vec::new(), String::new()),
+            },
+            "PAUSE" => match session.as_mut() {
+                Some(current) => {
+                    if let Some(handle) = current.streaming.take() {
+                        handle.abort();
+                    }
+                    ("200 OK", vec![("Session".to_string(), current.id.clone())], String::new())
+                }
+                None => ("455 Method Not Valid in This State", Vec::new(), String::new()),
+            },
+            "TEARDOWN" => {
+                session.take();
+                let response = format_response("200 OK", &cseq, &[], "");
+                writer.lock().await.write_all(response.as_bytes()).await?;
+                break;
+            }
+            "GET_PARAMETER" | "SET_PARAMETER" => {
+                let headers = session_id.map(|id| vec![("Session".to_string(), id)]).unwrap_or_default();
+                ("200 OK", headers, String::new())
+            }
+            _ => ("501 Not Implemented", Vec::new(), String::new()),
+        };
+
+        let response = format_response(status, &cseq, &headers, &body);
+        writer.lock().await.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+// Legge la prossima richiesta RTSP, scartando i pacchetti RTCP interleaved inviati dal client.
+async fn read_request(reader: &mut BufReader<OwnedReadHalf>) -> io::Result<Option<RtspRequest>> {
+    loop {
+        let first = match reader.fill_buf().await? {
+            [] => return Ok(None),
+            buf => buf[0],
+        };
+        if first != b'$' {
+            break;
+        }
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header).await?;
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut discarded = vec![0u8; len];
+        reader.read_exact(&mut discarded).await?;
+    }
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let url = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    let mut head_size = request_line.len();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        head_size += line.len();
+        if head_size > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Intestazione RTSP troppo grande"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    // Il corpo (es. SET_PARAMETER) non viene usato ma va consumato
+    if let Some(len) = headers.get("content-length").and_then(|v| v.parse::<usize>().ok()) {
+        let mut body = vec![0u8; len.min(MAX_REQUEST_HEAD)];
+        reader.read_exact(&mut body).await?;
+    }
+
+    Ok(Some(RtspRequest { method, url, headers }))
+}
+
+fn format_response(status: &str, cseq: &str, headers: &[(String, String)], body: &str) -> String {
+    let mut response = format!("RTSP/1.0 {}\r\nCSeq: {}\r\nServer: Screencast App\r\n", status, cseq);
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() {
+        response.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    response.push_str("\r\n");
+    response.push_str(body);
+    response
+}
+
+async fn setup_transport(
+    transport: &str,
+    peer: SocketAddr,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    ssrc: u32,
+) -> io::Result<(RtpSink, String)> {
+    let params: Vec<&str> = transport.split(',').next().unwrap_or_default().split(';').collect();
+    let param = |name: &str| {
+        params.iter()
+            .find_map(|p| p.strip_prefix(name).and_then(|v| v.strip_prefix('=')))
+            .map(|v| v.to_string())
+    };
+    let port_pair = |value: String| -> io::Result<(u16, u16)> {
+        let mut ports = value.split('-').map(|p| p.trim().parse::<u16>());
+        match (ports.next(), ports.next()) {
+            (Some(Ok(first)), Some(Ok(second))) => Ok((first, second)),
+            (Some(Ok(first)), None) => Ok((first, first.saturating_add(1))),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Porte del trasporto non valide")),
+        }
+    };
+
+    if params.first().map(|p| p.starts_with("RTP/AVP/TCP")).unwrap_or(false) {
+        let (rtp_channel, rtcp_channel) = match param("interleaved") {
+            Some(value) => port_pair(value)?,
+            None => (0, 1),
+        };
+        let reply = format!("RTP/AVP/TCP;unicast;interleaved={}-{};ssrc={:08X}", rtp_channel, rtcp_channel, ssrc);
+        return Ok((RtpSink::Interleaved { writer, channel: rtp_channel as u8 }, reply));
+    }
+
+    let client_ports = param("client_port")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "client_port mancante"))?;
+    let (rtp_port, rtcp_port) = port_pair(client_ports)?;
+    let bind_ip: IpAddr = if peer.is_ipv4() { Ipv4Addr::UNSPECIFIED.into() } else { Ipv6Addr::UNSPECIFIED.into() };
+    let socket = UdpSocket::bind(SocketAddr::new(bind_ip, 0)).await?;
+    let server_port = socket.local_addr()?.port();
+    let reply = format!(
+        "RTP/AVP;unicast;client_port={}-{};server_port={}-{};ssrc={:08X}",
+        rtp_port, rtcp_port, server_port, server_port.wrapping_add(1), ssrc
+    );
+    Ok((RtpSink::Udp { socket, destination: SocketAddr::new(peer.ip(), rtp_port) }, reply))
+}
+
+async fn stream_rtp(
+    sink: Arc<RtpSink>,
+    mut rx: broadcast::Receiver<Arc<[u8]>>,
+    ssrc: u32,
+    downscaler: &Downscaler,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let start = Instant::now();
+    let mut sequence: u16 = unix_nanos() as u16;
+    let mut warned = false;
+    let mut downscale_logged = false;
+
+    while !stop_signal.load(Ordering::SeqCst) && !terminate.load(Ordering::SeqCst) {
+        let packet = match rx.recv().await {
+            Ok(packet) => packet,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                eprintln!("Avviso: client RTSP in ritardo, saltati {} frame", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let jpeg = match protocol::parse_packet(&packet) {
+            protocol::Packet::Frame(jpeg) => jpeg,
+            protocol::Packet::KeepAlive | protocol::Packet::Resolution(..) | protocol::Packet::Annotations(_) => continue,
+            protocol::Packet::EndOfStream => break,
+        };
+        let mut frame = match parse_jpeg(jpeg) {
+            Ok(frame) => frame,
+            Err(e) => {
+                if !warned {
+                    eprintln!("Frame non inviabile via RTP/JPEG: {}", e);
+                    warned = true;
+                }
+                continue;
+            }
+        };
+        let downscaled;
+        if frame.width as usize > RTP_JPEG_MAX_SIZE || frame.height as usize > RTP_JPEG_MAX_SIZE {
+            downscaled = match downscaler.downscale(&packet).await {
+                Some(packet) => packet,
+                None => {
+                    eprintln!("Impossibile ridurre il frame di {}x{} per RTP/JPEG", frame.width, frame.height);
+                    continue;
+                }
+            };
+            let (width, height) = (frame.width, frame.height);
+            frame = match protocol::parse_packet(&downscaled) {
+                protocol::Packet::Frame(jpeg) => match parse_jpeg(jpeg) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        eprintln!("Frame ridotto non inviabile via RTP/JPEG: {}", e);
+                        continue;
+                    }
+                },
+                _ => continue,
+            };
+            if !downscale_logged {
+                println!("Frame di {}x{} ridotti a {}x{} per il client RTSP", width, height, frame.width, frame.height);
+                downscale_logged = true;
+            }
+        }
+        let timestamp = (start.elapsed().as_secs_f64() * RTP_CLOCK_RATE) as u32;
+        for rtp_packet in packetize(&frame, &mut sequence, timestamp, ssrc) {
+            sink.send(&rtp_packet).await?;
+        }
+    }
+    Ok(())
+}
+
+// Estrae tabelle di quantizzazione, dimensioni e dati di scansione da un JPEG baseline.
+fn parse_jpeg(data: &[u8]) -> Result<JpegFrame<'_>, &'static str> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != 0xD8 {
+        return Err("marker SOI mancante");
+    }
+
+    let mut tables: [Option<&[u8]>; 4] = [None; 4];
+    let mut size_and_type = None;
+    let mut pos = 2;
+
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return Err("marker JPEG non valido");
+        }
+        let marker = data[pos + 1];
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment_end = pos + 2 + len;
+        if len < 2 || segment_end > data.len() {
+            return Err("segmento JPEG troncato");
+        }
+        let segment = &data[pos + 4..segment_end];
+
+        match marker {
+            0xDB => {
+                let mut offset = 0;
+                while offset < segment.len() {
+                    let precision = segment[offset] >> 4;
+                    let id = (segment[offset] & 0x0F) as usize;
+                    if precision != 0 || id > 3 || offset + 65 > segment.len() {
+                        return Err("tabella di quantizzazione non supportata");
+                    }
+                    tables[id] = Some(&segment[offset + 1..offset + 65]);
+                    offset += 65;
+                }
+            }
+            0xC0 => {
+                if segment.len() < 15 || segment[5] != 3 {
+                    return Err("sono supportati solo JPEG a tre componenti");
+                }
+                let height = u16::from_be_bytes([segment[1], segment[2]]);
+                let width = u16::from_be_bytes([segment[3], segment[4]]);
+                let jpeg_type = match (segment[7], segment[10], segment[13]) {
+                    (0x21, 0x11, 0x11) => 0,
+                    (0x22, 0x11, 0x11) => 1,
+                    _ => return Err("campionamento cromatico non supportato (serve 4:2:2 o 4:2:0)"),
+                };
+                size_and_type = Some((width, height, jpeg_type));
+            }
+            0xC1..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                return Err("sono supportati solo JPEG baseline");
+            }
+            0xDD if segment.len() >= 2 && u16::from_be_bytes([segment[0], segment[1]]) != 0 => {
+                return Err("restart marker non supportati");
+            }
+            0xDA => {
+                let (width, height, jpeg_type) = size_and_type.ok_or("intestazione SOF mancante")?;
+                let mut quant_tables = Vec::with_capacity(128);
+                for table in tables.iter().take(2) {
+                    quant_tables.extend_from_slice(table.ok_or("tabelle di quantizzazione mancanti")?);
+                }
+                let scan_end = if data.ends_with(&[0xFF, 0xD9]) { data.len() - 2 } else { data.len() };
+                return Ok(JpegFrame {
+                    jpeg_type,
+                    width,
+                    height,
+                    quant_tables,
+                    scan: &data[segment_end..scan_end.max(segment_end)],
+                });
+            }
+            _ => {}
+        }
+        pos = segment_end;
+    }
+
+    Err("dati di scansione mancanti")
+}
+
+// Ultimo frame ridotto, condiviso da tutte le sessioni RTSP: un frame troppo grande viene
+// decodificato e ricompresso una sola volta, qualunque sia il numero di client. Le sessioni
+// che chiedono lo stesso frame mentre la riduzione è in corso ne attendono il risultato.
+// Pacchetto originale e sua versione ridotta (None se la riduzione non è riuscita).
+type DownscaledPacket = (Arc<[u8]>, Option<Arc<[u8]>>);
+
+#[derive(Default)]
+struct Downscaler {
+    last: Mutex<Option<DownscaledPacket>>,
+}
+
+impl Downscaler {
+    async fn downscale(&self, packet: &Arc<[u8]>) -> Option<Arc<[u8]>> {
+        let mut last = self.last.lock().await;
+        if let Some((_, downscaled)) = last.as_ref().filter(|(source, _)| Arc::ptr_eq(source, packet)) {
+            return downscaled.clone();
+        }
+        let source = Arc::clone(packet);
+        let downscaled = tokio::task::spawn_blocking(move || match protocol::parse_packet(&source) {
+            protocol::Packet::Frame(jpeg) => downscale_jpeg(jpeg).map(Arc::from),
+            _ => None,
+        }).await.ok().flatten();
+        *last = Some((Arc::clone(packet), downscaled.clone()));
+        downscaled
+    }
+}
+
+// Decodifica il frame e lo ricomprime entro RTP_JPEG_MAX_SIZE, come pacchetto del canale broadcast.
+fn downscale_jpeg(jpeg: &[u8]) -> Option<Vec<u8>> {
+    let rgb = image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg).ok()?.into_rgb8();
+    let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+    let (output_width, output_height) = OutputScale::Fit(RTP_JPEG_MAX_SIZE as u32, RTP_JPEG_MAX_SIZE as u32).output_size(width, height);
+    let scaled = scaling::resize_rgb(rgb.as_raw(), width, height, output_width, output_height)?;
+    let mut packet = Vec::new();
+    pipeline::compress_to_jpeg(&scaled, output_width, output_height, &mut packet).ok()?;
+    Some(packet)
+}
+
+fn packetize(frame: &JpegFrame, sequence: &mut u16, timestamp: u32, ssrc: u32) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    let mut offset = 0;
+
+    while offset < frame.scan.len() {
+        let mut packet = Vec::with_capacity(RTP_MAX_PACKET);
+        // Header RTP: versione 2, marker sull'ultimo frammento del frame
+        packet.extend_from_slice(&[0x80, RTP_JPEG_PAYLOAD_TYPE]);
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&ssrc.to_be_bytes());
+
+        // Header JPEG (RFC 2435 3.1) con Q=255: tabelle di quantizzazione nel primo frammento
+        packet.push(0);
+        packet.extend_from_slice(&(offset as u32).to_be_bytes()[1..]);
+        packet.extend_from_slice(&[
+            frame.jpeg_type,
+            255,
+            (frame.width as usize).div_ceil(8) as u8,
+            (frame.height as usize).div_ceil(8) as u8,
+        ]);
+        if offset == 0 {
+            packet.extend_from_slice(&[0, 0]);
+            packet.extend_from_slice(&(frame.quant_tables.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&frame.quant_tables);
+        }
+
+        let chunk = (RTP_MAX_PACKET - packet.len()).min(frame.scan.len() - offset);
+        packet.extend_from_slice(&frame.scan[offset..offset + chunk]);
+        offset += chunk;
+        if offset == frame.scan.len() {
+            packet[1] |= 0x80;
+        }
+
+        packets.push(packet);
+        *sequence = sequence.wrapping_add(1);
+    }
+    packets
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_frames_are_downscaled_for_rtp() {
+        let (width, height) = (2560, 1440);
+        let rgb = vec![128u8; width * height * 3];
+        let mut packet = Vec::new();
+        pipeline::compress_to_jpeg(&rgb, width, height, &mut packet).unwrap();
+
+        let downscaled = downscale_jpeg(&packet[4..]).unwrap();
+        let jpeg = match protocol::parse_packet(&downscaled) {
+            protocol::Packet::Frame(jpeg) => jpeg,
+            _ => panic!("pacchetto non valido"),
+        };
+        let frame = parse_jpeg(jpeg).unwrap();
+        assert_eq!((frame.width, frame.height), (2040, 1148));
+        assert_eq!(frame.jpeg_type, 1);
+    }
+
+    #[tokio::test]
+    async fn each_frame_is_downscaled_once() {
+        let (width, height) = (2100, 64);
+        let mut packet = Vec::new();
+        pipeline::compress_to_jpeg(&vec![64u8; width * height * 3], width, height, &mut packet).unwrap();
+        let packet: Arc<[u8]> = packet.into();
+
+        // Più sessioni sullo stesso frame ricevono lo stesso pacchetto ridotto
+        let downscaler = Downscaler::default();
+        let first = downscaler.downscale(&packet).await.unwrap();
+        let second = downscaler.downscale(&Arc::clone(&packet)).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // Un frame nuovo, anche con lo stesso contenuto, viene ridotto di nuovo
+        let next: Arc<[u8]> = packet.to_vec().into();
+        assert!(!Arc::ptr_eq(&first, &downscaler.downscale(&next).await.unwrap()));
+    }
+}
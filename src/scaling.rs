@@ -0,0 +1,205 @@
+// Risoluzione del flusso in uscita. Il frame non viene mai ingrandito oltre la dimensione catturata.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputScale {
+    #[default]
+    Native,
+    Width(u32),
+    Percent(u32),
+    Fit(u32, u32),
+}
+
+impl OutputScale {
+    pub fn label(&self) -> &'static str {
+        match self {
+            OutputScale::Native => "Nativa",
+            OutputScale::Width(_) => "Larghezza fissa",
+            OutputScale::Percent(_) => "Percentuale",
+            OutputScale::Fit(_, _) => "Adatta a LxA",
+        }
+    }
+
+    // Accetta "nativa", una larghezza ("1280"), una percentuale ("50%") o un riquadro ("1920x1080").
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_lowercase();
+        if value == "nativa" || value == "native" {
+            return Some(OutputScale::Native);
+        }
+        if let Some(percent) = value.strip_suffix('%') {
+            return percent.parse().ok().filter(|p| (1..=100).contains(p)).map(OutputScale::Percent);
+        }
+        if let Some((width, height)) = value.split_once('x') {
+            let width = width.parse().ok().filter(|w| *w > 0)?;
+            let height = height.parse().ok().filter(|h| *h > 0)?;
+            return Some(OutputScale::Fit(width, height));
+        }
+        value.parse().ok().filter(|w| *w > 0).map(OutputScale::Width)
+    }
+
+    pub fn output_size(&self, width: usize, height: usize) -> (usize, usize) {
+        let factor = match *self {
+            OutputScale::Native => 1.0,
+            OutputScale::Width(target) => target as f64 / width as f64,
+            OutputScale::Percent(percent) => percent as f64 / 100.0,
+            OutputScale::Fit(max_width, max_height) => {
+                (max_width as f64 / width as f64).min(max_height as f64 / height as f64)
+            }
+        };
+        if factor >= 1.0 {
+            return (width, height);
+        }
+        (
+            ((width as f64 * factor).round() as usize).max(1),
+            ((height as f64 * factor).round() as usize).max(1),
+        )
+    }
+}
+
+// Ridimensiona un frame RGB compatto. Restituisce None se il buffer non corrisponde alle dimensioni.
+pub fn resize_rgb(rgb: &[u8], width: usize, height: usize, output_width: usize, output_height: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    Resizer::default().resize(rgb, width, height, output_width, output_height, &mut output).then_some(output)
+}
+
+// Filtro bicubico (Catmull-Rom): nitido sul testo e molto più rapido di Lanczos sui frame 4K.
+// Ridimensionamento separabile per la pipeline: pesi, buffer
+// intermedio e uscita restano allocati tra un frame e l'altro e si ricalcolano solo quando
+// cambiano le dimensioni.
+#[derive(Default)]
+pub struct Resizer {
+    sizes: (usize, usize, usize, usize),
+    horizontal: Vec<(usize, Vec<f32>)>,
+    vertical: Vec<(usize, Vec<f32>)>,
+    intermediate: Vec<f32>,
+    row: Vec<f32>,
+}
+
+impl Resizer {
+    // Restituisce false se il buffer non corrisponde alle dimensioni.
+    pub fn resize(
+        &mut self,
+        rgb: &[u8],
+        width: usize,
+        height: usize,
+        output_width: usize,
+        output_height: usize,
+        output: &mut Vec<u8>,
+    ) -> bool {
+        if width == 0 || height == 0 || output_width == 0 || output_height == 0 || rgb.len() != width * height * 3 {
+            return false;
+        }
+        let sizes = (width, height, output_width, output_height);
+        if self.sizes != sizes {
+            self.sizes = sizes;
+            self.horizontal = weights(width, output_width);
+            self.vertical = weights(height, output_height);
+        }
+
+        // Passo orizzontale: height righe larghe output_width
+        self.intermediate.clear();
+        self.intermediate.resize(output_width * height * 3, 0.0);
+        for (src_row, dst_row) in rgb.chunks_exact(width * 3).zip(self.intermediate.chunks_exact_mut(output_width * 3)) {
+            for ((start, taps), dst) in self.horizontal.iter().zip(dst_row.chunks_exact_mut(3)) {
+                let mut sum = [0.0f32; 3];
+                for (pixel, weight) in src_row[start * 3..].chunks_exact(3).zip(taps) {
+                    sum[0] += pixel[0] as f32 * weight;
+                    sum[1] += pixel[1] as f32 * weight;
+                    sum[2] += pixel[2] as f32 * weight;
+                }
+                dst.copy_from_slice(&sum);
+            }
+        }
+
+        // Passo verticale, direttamente nel buffer di uscita
+        let row_len = output_width * 3;
+        output.clear();
+        output.resize(row_len * output_height, 0);
+        self.row.clear();
+        self.row.resize(row_len, 0.0);
+        for ((start, taps), dst_row) in self.vertical.iter().zip(output.chunks_exact_mut(row_len)) {
+            // Righe intere alla volta: la memoria intermedia viene letta in sequenza
+            self.row.fill(0.0);
+            for (src_row, weight) in self.intermediate[start * row_len..].chunks_exact(row_len).zip(taps) {
+                for (sum, value) in self.row.iter_mut().zip(src_row) {
+                    *sum += value * weight;
+                }
+            }
+            for (dst, sum) in dst_row.iter_mut().zip(&self.row) {
+                *dst = sum.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        true
+    }
+}
+
+fn catmull_rom(x: f32) -> f32 {
+    let x = x.abs();
+    if x < 1.0 {
+        1.5 * x * x * x - 2.5 * x * x + 1.0
+    } else if x < 2.0 {
+        -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+    } else {
+        0.0
+    }
+}
+
+// Per ogni pixel in uscita: primo pixel sorgente coinvolto e pesi normalizzati.
+// In riduzione il nucleo si allarga in proporzione per non perdere dettagli.
+fn weights(input: usize, output: usize) -> Vec<(usize, Vec<f32>)> {
+    let ratio = input as f32 / output as f32;
+    let spread = ratio.max(1.0);
+    let support = 2.0 * spread;
+    (0..output)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * ratio;
+            let start = ((center - support).floor().max(0.0) as usize).min(input - 1);
+            let end = ((center + support).ceil() as usize).clamp(start + 1, input);
+            let mut taps: Vec<f32> = (start..end)
+                .map(|j| catmull_rom((j as f32 + 0.5 - center) / spread))
+                .collect();
+            let total: f32 = taps.iter().sum();
+            if total != 0.0 {
+                taps.iter_mut().for_each(|w| *w /= total);
+            }
+            (start, taps)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::imageops::{self, FilterType};
+    use image::{ImageBuffer, Rgb};
+
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [(x * 255 / width) as u8, (y * 255 / height) as u8, ((x + y) % 256) as u8]))
+            .collect()
+    }
+
+    #[test]
+    fn resizer_matches_the_image_filter() {
+        let (width, height) = (97, 61);
+        let rgb = gradient(width, height);
+        let image: ImageBuffer<Rgb<u8>, &[u8]> = ImageBuffer::from_raw(width as u32, height as u32, rgb.as_slice()).unwrap();
+        for (output_width, output_height) in [(48, 30), (33, 61), (97, 20)] {
+            let expected = imageops::resize(&image, output_width as u32, output_height as u32, FilterType::CatmullRom).into_raw();
+            let actual = resize_rgb(&rgb, width, height, output_width, output_height).unwrap();
+            assert_eq!(actual.len(), expected.len());
+            let worst = actual.iter().zip(&expected).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+            assert!(worst <= 2, "{}x{}: differenza massima {}", output_width, output_height, worst);
+        }
+    }
+
+    #[test]
+    fn resizer_reuses_its_buffers() {
+        let rgb = gradient(64, 48);
+        let mut resizer = Resizer::default();
+        let mut output = Vec::new();
+        assert!(resizer.resize(&rgb, 64, 48, 32, 24, &mut output));
+        let (pointer, capacity) = (output.as_ptr(), output.capacity());
+        assert!(resizer.resize(&rgb, 64, 48, 32, 24, &mut output));
+        assert_eq!((output.as_ptr(), output.capacity()), (pointer, capacity));
+        assert!(!resizer.resize(&rgb[3..], 64, 48, 32, 24, &mut output));
+    }
+}
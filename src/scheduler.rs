@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FPS: u32 = 30;
+pub const MIN_FPS: u32 = 1;
+pub const MAX_FPS: u32 = 60;
+
+// Quando lo schermo non cambia o è oscurato si invia un keep-alive solo a questo intervallo,
+// abbastanza spesso da non far scattare il timeout di pausa dei receiver (2 s).
+const IDLE_HEARTBEAT: Duration = Duration::from_millis(1000);
+
+// Scandisce la cattura a intervalli fissi rispetto all'avvio, quindi senza deriva: se una
+// cattura richiede più di un periodo i tick persi vengono saltati invece di accumularsi.
+// Gira sul thread di cattura, fuori dal runtime tokio.
+pub struct FrameScheduler {
+    target_fps: Arc<AtomicU32>,
+    current_fps: u32,
+    next_tick: Instant,
+    last_sent: Option<Instant>,
+}
+
+impl FrameScheduler {
+    pub fn new(target_fps: Arc<AtomicU32>) -> Self {
+        let fps = clamp_fps(target_fps.load(Ordering::SeqCst));
+        Self {
+            target_fps,
+            current_fps: fps,
+            next_tick: Instant::now(),
+            last_sent: None,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        let fps = clamp_fps(self.target_fps.load(Ordering::SeqCst));
+        if fps != self.current_fps {
+            println!("Frame rate obiettivo: {} fps", fps);
+            self.current_fps = fps;
+            self.next_tick = Instant::now();
+        }
+        let now = Instant::now();
+        if self.next_tick > now {
+            thread::sleep(self.next_tick - now);
+        }
+        let period = Duration::from_secs_f64(1.0 / fps as f64);
+        let now = Instant::now();
+        while self.next_tick <= now {
+            self.next_tick += period;
+        }
+    }
+
+    pub fn mark_sent(&mut self) {
+        self.last_sent = Some(Instant::now());
+    }
+
+    pub fn heartbeat_due(&self) -> bool {
+        self.last_sent.is_none_or(|sent| sent.elapsed() >= IDLE_HEARTBEAT)
+    }
+}
+
+pub fn clamp_fps(fps: u32) -> u32 {
+    fps.clamp(MIN_FPS, MAX_FPS)
+}
@@ -0,0 +1,244 @@
+use std::io;
+use scrap::{Capturer, Display};
+
+// Sorgente della cattura: un singolo display oppure tutti i display composti in un'unica
+// immagine secondo la loro disposizione. scrap non espone la posizione dei monitor: su X11
+// la si legge da RandR, da cui scrap ricava lo stesso elenco; altrove, o se RandR non
+// risponde, si assume che siano affiancati orizzontalmente nell'ordine di enumerazione.
+
+// Indice speciale che seleziona tutti i display insieme
+pub const ALL_DISPLAYS: usize = usize::MAX;
+
+pub fn display_name(display_index: usize) -> String {
+    if display_index == ALL_DISPLAYS {
+        "Tutti i display".to_string()
+    } else {
+        format!("Display {}", display_index + 1)
+    }
+}
+
+// Posizione e dimensioni di un display in coordinate dello schermo virtuale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DisplayGeometry {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width as i32 && y >= self.y && y < self.y + self.height as i32
+    }
+}
+
+// Disposizione dei display nell'ordine di scrap, per accorgersi di cambi di modalità o di
+// monitor collegati e scollegati e per sapere dove si trova ciascuno.
+pub fn display_layout() -> Vec<DisplayGeometry> {
+    Display::all().map(|displays| arrange(&displays)).unwrap_or_default()
+}
+
+fn arrange(displays: &[Display]) -> Vec<DisplayGeometry> {
+    let sizes: Vec<(usize, usize)> = displays.iter().map(|d| (d.width(), d.height())).collect();
+    place(&sizes, monitor_layout())
+}
+
+// Le posizioni di RandR si usano solo se corrispondono ai display di scrap, uno per uno.
+fn place(sizes: &[(usize, usize)], monitors: Option<Vec<DisplayGeometry>>) -> Vec<DisplayGeometry> {
+    let matches = |monitors: &Vec<DisplayGeometry>| {
+        monitors.len() == sizes.len() && monitors.iter().zip(sizes).all(|(m, &size)| (m.width, m.height) == size)
+    };
+    if let Some(monitors) = monitors.filter(matches) {
+        return monitors;
+    }
+    let mut left = 0;
+    sizes.iter()
+        .map(|&(width, height)| {
+            let display = DisplayGeometry { x: left, y: 0, width, height };
+            left += width as i32;
+            display
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn monitor_layout() -> Option<Vec<DisplayGeometry>> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::randr::ConnectionExt;
+    use x11rb::rust_connection::RustConnection;
+
+    let (conn, _) = RustConnection::connect(None).ok()?;
+    let mut monitors = Vec::new();
+    // Stesso ordine di scrap: i monitor attivi di ogni schermo X, uno schermo dopo l'altro
+    for screen in &conn.setup().roots {
+        let reply = conn.randr_get_monitors(screen.root, true).ok()?.reply().ok()?;
+        monitors.extend(reply.monitors.iter().map(|m| DisplayGeometry {
+            x: m.x as i32,
+            y: m.y as i32,
+            width: m.width as usize,
+            height: m.height as usize,
+        }));
+    }
+    Some(monitors)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn monitor_layout() -> Option<Vec<DisplayGeometry>> {
+    None
+}
+
+// Rettangolo catturato per un indice: il display stesso o, con tutti i display, il più piccolo
+// rettangolo che li contiene; le zone non coperte da un monitor restano nere.
+pub fn source_geometry(layout: &[DisplayGeometry], display_index: usize) -> Option<DisplayGeometry> {
+    if display_index != ALL_DISPLAYS {
+        return layout.get(display_index).copied();
+    }
+    let left = layout.iter().map(|d| d.x).min()?;
+    let top = layout.iter().map(|d| d.y).min()?;
+    let right = layout.iter().map(|d| d.x + d.width as i32).max()?;
+    let bottom = layout.iter().map(|d| d.y + d.height as i32).max()?;
+    Some(DisplayGeometry { x: left, y: top, width: (right - left) as usize, height: (bottom - top) as usize })
+}
+
+// Display che contiene il punto, in coordinate dello schermo virtuale.
+pub fn display_at(layout: &[DisplayGeometry], x: i32, y: i32) -> Option<usize> {
+    layout.iter().position(|display| display.contains(x, y))
+}
+
+struct Source {
+    capturer: Capturer,
+    // Posizione del display nel canvas
+    offset_x: usize,
+    offset_y: usize,
+    height: usize,
+    // Il display ha già fornito un frame: la sua zona del canvas contiene l'ultima immagine valida
+    captured: bool,
+}
+
+pub struct ScreenCapturer {
+    sources: Vec<Source>,
+    width: usize,
+    height: usize,
+    // Posizione nello schermo virtuale, per cursore e finestre
+    origin: (i32, i32),
+    // Immagine composta, usata solo con più display
+    canvas: Vec<u8>,
+}
+
+impl ScreenCapturer {
+    pub fn open(display_index: usize) -> io::Result<Self> {
+        let displays = Display::all()?;
+        if display_index != ALL_DISPLAYS && display_index >= displays.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Indice del display non valido"));
+        }
+        let layout = arrange(&displays);
+        let bounds = source_geometry(&layout, display_index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Nessun display disponibile"))?;
+
+        let mut sources = Vec::new();
+        for (index, (display, geometry)) in displays.into_iter().zip(&layout).enumerate() {
+            if display_index != ALL_DISPLAYS && index != display_index {
+                continue;
+            }
+            sources.push(Source {
+                capturer: Capturer::new(display)?,
+                offset_x: (geometry.x - bounds.x) as usize,
+                offset_y: (geometry.y - bounds.y) as usize,
+                height: geometry.height,
+                captured: false,
+            });
+        }
+        let (width, height) = (bounds.width, bounds.height);
+        let canvas = if sources.len() > 1 { vec![0; width * height * 4] } else { Vec::new() };
+        Ok(Self { sources, width, height, origin: (bounds.x, bounds.y), canvas })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn origin(&self) -> (i32, i32) {
+        self.origin
+    }
+
+    // Passa a `f` l'immagine catturata e la sua stride in byte. Con un solo display il frame di
+    // scrap viene usato direttamente; con più display i frame nuovi vengono copiati nel canvas,
+    // dove ogni display mantiene l'ultima immagine ricevuta, e WouldBlock indica che nessun
+    // display è cambiato o che non tutti hanno ancora fornito un frame. Un frame più piccolo del previsto (cambio
+    // di risoluzione non ancora rilevato) produce InvalidData.
+    pub fn with_frame<R>(&mut self, f: impl FnOnce(&[u8], usize) -> R) -> io::Result<R> {
+        let canvas_stride = self.width * 4;
+        if let [source] = self.sources.as_mut_slice() {
+            let frame = source.capturer.frame()?;
+            let stride = frame.len() / source.height.max(1);
+            if stride < canvas_stride {
+                return Err(unexpected_size());
+            }
+            return Ok(f(&frame, stride));
+        }
+
+        let mut updated = false;
+        for source in &mut self.sources {
+            let display_width = source.capturer.width();
+            let frame = match source.capturer.frame() {
+                Ok(frame) => frame,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            };
+            let stride = frame.len() / source.height.max(1);
+            if stride < display_width * 4 {
+                return Err(unexpected_size());
+            }
+            for y in 0..source.height {
+                let start = (source.offset_y + y) * canvas_stride + source.offset_x * 4;
+                self.canvas[start..start + display_width * 4].copy_from_slice(&frame[y * stride..y * stride + display_width * 4]);
+            }
+            source.captured = true;
+            updated = true;
+        }
+        if !updated || self.sources.iter().any(|source| !source.captured) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(f(&self.canvas, canvas_stride))
+    }
+}
+
+fn unexpected_size() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Dimensione del frame inattesa")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry(x: i32, y: i32, width: usize, height: usize) -> DisplayGeometry {
+        DisplayGeometry { x, y, width, height }
+    }
+
+    #[test]
+    fn randr_positions_are_used_when_they_match() {
+        // Portatile sotto un monitor più grande, spostato a destra
+        let monitors = vec![geometry(320, 1440, 1920, 1080), geometry(0, 0, 2560, 1440)];
+        let sizes = [(1920, 1080), (2560, 1440)];
+        assert_eq!(place(&sizes, Some(monitors.clone())), monitors);
+        // Elenco diverso da quello di scrap: si torna ai display affiancati
+        let side_by_side = vec![geometry(0, 0, 1920, 1080), geometry(1920, 0, 2560, 1440)];
+        assert_eq!(place(&sizes, Some(monitors[..1].to_vec())), side_by_side);
+        assert_eq!(place(&sizes, None), side_by_side);
+    }
+
+    #[test]
+    fn stacked_layout() {
+        let layout = [geometry(320, 1440, 1920, 1080), geometry(0, 0, 2560, 1440)];
+        assert_eq!(source_geometry(&layout, ALL_DISPLAYS), Some(geometry(0, 0, 2560, 2520)));
+        assert_eq!(source_geometry(&layout, 0), Some(layout[0]));
+        assert_eq!(display_at(&layout, 400, 2000), Some(0));
+        assert_eq!(display_at(&layout, 400, 100), Some(1));
+        // Zona del canvas non coperta da alcun monitor
+        assert_eq!(display_at(&layout, 100, 2000), None);
+    }
+}
@@ -0,0 +1,108 @@
+use eframe::egui::{pos2, Pos2, Rect};
+
+// Selezione dell'area condivisa in pixel fisici. L'anteprima è disegnata in punti egui, che
+// sui display scalati (HiDPI) non coincidono con i pixel, ed è ridimensionata e centrata
+// nella finestra: le conversioni passano sempre dalle dimensioni reali dello screenshot.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum AspectLock {
+    #[default]
+    Free,
+    Wide,
+    Standard,
+}
+
+impl AspectLock {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AspectLock::Free => "Libere",
+            AspectLock::Wide => "16:9",
+            AspectLock::Standard => "4:3",
+        }
+    }
+
+    pub fn ratio(&self) -> Option<(usize, usize)> {
+        match self {
+            AspectLock::Free => None,
+            AspectLock::Wide => Some((16, 9)),
+            AspectLock::Standard => Some((4, 3)),
+        }
+    }
+}
+
+// Corrispondenza tra l'immagine di anteprima sullo schermo e i pixel del display catturato.
+pub struct PreviewMapping {
+    image_rect: Rect,
+    display_size: (usize, usize),
+}
+
+impl PreviewMapping {
+    pub fn new(image_rect: Rect, display_size: (usize, usize)) -> Self {
+        Self { image_rect, display_size }
+    }
+
+    // Punto dell'anteprima -> pixel del display, arrotondato al bordo di pixel più vicino.
+    pub fn to_pixels(&self, pos: Pos2) -> Pos2 {
+        let x = (pos.x - self.image_rect.min.x) / self.image_rect.width() * self.display_size.0 as f32;
+        let y = (pos.y - self.image_rect.min.y) / self.image_rect.height() * self.display_size.1 as f32;
+        pos2(
+            x.round().clamp(0.0, self.display_size.0 as f32),
+            y.round().clamp(0.0, self.display_size.1 as f32),
+        )
+    }
+
+    pub fn to_points(&self, pixel: Pos2) -> Pos2 {
+        pos2(
+            self.image_rect.min.x + pixel.x / self.display_size.0 as f32 * self.image_rect.width(),
+            self.image_rect.min.y + pixel.y / self.display_size.1 as f32 * self.image_rect.height(),
+        )
+    }
+
+    pub fn rect_to_points(&self, area: Rect) -> Rect {
+        Rect::from_min_max(self.to_points(area.min), self.to_points(area.max))
+    }
+}
+
+// Area in pixel interi come (x, y, larghezza, altezza), per la modifica numerica.
+pub fn area_to_xywh(area: Rect) -> (usize, usize, usize, usize) {
+    let x = area.min.x.max(0.0) as usize;
+    let y = area.min.y.max(0.0) as usize;
+    (x, y, (area.max.x.max(0.0) as usize).saturating_sub(x), (area.max.y.max(0.0) as usize).saturating_sub(y))
+}
+
+pub fn xywh_to_area(x: usize, y: usize, width: usize, height: usize) -> Rect {
+    Rect::from_min_max(pos2(x as f32, y as f32), pos2((x + width) as f32, (y + height) as f32))
+}
+
+// Applica proporzioni e dimensioni pari all'area restando dentro il display. L'angolo
+// `anchor` (quello da cui è partito il trascinamento) resta fermo, l'altro si adatta.
+// Restituisce None se l'area risultante è vuota.
+pub fn constrain(area: Rect, anchor: Pos2, lock: AspectLock, even: bool, display_size: (usize, usize)) -> Option<Rect> {
+    let (x, y, width, height) = area_to_xywh(area);
+    let (display_width, display_height) = display_size;
+    let x = x.min(display_width);
+    let y = y.min(display_height);
+    let (drawn_width, drawn_height) = (width.min(display_width - x), height.min(display_height - y));
+    let (mut width, mut height) = (drawn_width, drawn_height);
+
+    if let Some((ratio_w, ratio_h)) = lock.ratio() {
+        // Si riduce il lato in eccesso, così l'area non esce mai da quella disegnata
+        if width * ratio_h > height * ratio_w {
+            width = height * ratio_w / ratio_h;
+        } else {
+            height = width * ratio_h / ratio_w;
+        }
+    }
+    if even {
+        width -= width % 2;
+        height -= height % 2;
+    }
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    // Se l'ancora è sul lato destro o inferiore è l'angolo opposto a spostarsi verso di essa
+    let x = if anchor.x >= area.max.x { x + drawn_width - width } else { x };
+    let y = if anchor.y >= area.max.y { y + drawn_height - height } else { y };
+    Some(xywh_to_area(x, y, width, height))
+}
@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{interval, timeout, Duration};
+use crate::protocol;
+
+// Ogni pacchetto del protocollo viene diviso in frammenti da FRAGMENT_SIZE byte; ogni
+// FEC_GROUP_SIZE frammenti di dati segue un frammento di parità (XOR) che permette di
+// ricostruire un frammento perso nel gruppo.
+// I pacchetti di controllo (fine dello stream, risoluzione, keep-alive, annotazioni) hanno una
+// numerazione separata da quella dei frame, vengono inviati CONTROL_COPIES volte e non sono
+// scartati quando si completa un frame più recente.
+const MAGIC: [u8; 2] = *b"SC";
+const HEADER_SIZE: usize = 18;
+const FRAGMENT_SIZE: usize = 1200;
+const FEC_GROUP_SIZE: usize = 8;
+const MAX_PACKET_SIZE: usize = 10_000_000;
+const MAX_PENDING_FRAMES: usize = 8;
+const MAX_TRACKED_CONTROLS: usize = 32;
+const CONTROL_COPIES: usize = 2;
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+const HELLO_INTERVAL: Duration = Duration::from_secs(1);
+
+const KIND_DATA: u8 = 0;
+const KIND_PARITY: u8 = 1;
+const KIND_HELLO: u8 = 2;
+const KIND_ACK: u8 = 3;
+const KIND_BYE: u8 = 4;
+const KIND_CONTROL_DATA: u8 = 5;
+const KIND_CONTROL_PARITY: u8 = 6;
+
+struct FragmentHeader {
+    kind: u8,
+    frame_id: u32,
+    index: usize,
+    data_count: usize,
+    group_size: usize,
+    payload_len: usize,
+    total_len: usize,
+}
+
+impl FragmentHeader {
+    fn control(kind: u8) -> Self {
+        Self { kind, frame_id: 0, index: 0, data_count: 0, group_size: 0, payload_len: 0, total_len: 0 }
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&MAGIC);
+        buf.push(self.kind);
+        buf.extend_from_slice(&self.frame_id.to_be_bytes());
+        buf.extend_from_slice(&(self.index as u16).to_be_bytes());
+        buf.extend_from_slice(&(self.data_count as u16).to_be_bytes());
+        buf.push(self.group_size as u8);
+        buf.extend_from_slice(&(self.payload_len as u16).to_be_bytes());
+        buf.extend_from_slice(&(self.total_len as u32).to_be_bytes());
+    }
+
+    fn parse(datagram: &[u8]) -> Option<Self> {
+        if datagram.len() < HEADER_SIZE || datagram[0..2] != MAGIC {
+            return None;
+        }
+        let header = Self {
+            kind: datagram[2],
+            frame_id: u32::from_be_bytes([datagram[3], datagram[4], datagram[5], datagram[6]]),
+            index: u16::from_be_bytes([datagram[7], datagram[8]]) as usize,
+            data_count: u16::from_be_bytes([datagram[9], datagram[10]]) as usize,
+            group_size: datagram[11] as usize,
+            payload_len: u16::from_be_bytes([datagram[12], datagram[13]]) as usize,
+            total_len: u32::from_be_bytes([datagram[14], datagram[15], datagram[16], datagram[17]]) as usize,
+        };
+        if header.payload_len > FRAGMENT_SIZE || datagram.len() < HEADER_SIZE + header.payload_len {
+            return None;
+        }
+        Some(header)
+    }
+}
+
+fn control_datagram(kind: u8) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_SIZE);
+    FragmentHeader::control(kind).write(&mut datagram);
+    datagram
+}
+
+// Divide un pacchetto in datagrammi di dati seguiti, per ogni gruppo, dal frammento di parità.
+fn fragment(frame_id: u32, packet: &[u8], control: bool) -> Vec<Vec<u8>> {
+    let (data_kind, parity_kind) = if control { (KIND_CONTROL_DATA, KIND_CONTROL_PARITY) } else { (KIND_DATA, KIND_PARITY) };
+    let data_count = packet.len().div_ceil(FRAGMENT_SIZE).max(1);
+    let mut datagrams = Vec::with_capacity(data_count + data_count.div_ceil(FEC_GROUP_SIZE));
+
+    for (group, chunks) in packet.chunks(FRAGMENT_SIZE).collect::<Vec<_>>().chunks(FEC_GROUP_SIZE).enumerate() {
+        let mut parity = [0u8; FRAGMENT_SIZE];
+        for (offset, chunk) in chunks.iter().enumerate() {
+            let header = FragmentHeader {
+                kind: data_kind,
+                frame_id,
+                index: group * FEC_GROUP_SIZE + offset,
+                data_count,
+                group_size: FEC_GROUP_SIZE,
+                payload_len: chunk.len(),
+                total_len: packet.len(),
+            };
+            let mut datagram = Vec::with_capacity(HEADER_SIZE + chunk.len());
+            header.write(&mut datagram);
+            datagram.extend_from_slice(chunk);
+            datagrams.push(datagram);
+            parity.iter_mut().zip(chunk.iter()).for_each(|(p, b)| *p ^= b);
+        }
+        let header = FragmentHeader {
+            kind: parity_kind,
+            frame_id,
+            index: group,
+            data_count,
+            group_size: FEC_GROUP_SIZE,
+            payload_len: FRAGMENT_SIZE,
+            total_len: packet.len(),
+        };
+        let mut datagram = Vec::with_capacity(HEADER_SIZE + FRAGMENT_SIZE);
+        header.write(&mut datagram);
+        datagram.extend_from_slice(&parity);
+        datagrams.push(datagram);
+    }
+    datagrams
+}
+
+struct PartialFrame {
+    total_len: usize,
+    group_size: usize,
+    fragments: Vec<Option<Vec<u8>>>,
+    parity: Vec<Option<Vec<u8>>>,
+}
+
+impl PartialFrame {
+    fn new(header: &FragmentHeader) -> Self {
+        Self {
+            total_len: header.total_len,
+            group_size: header.group_size,
+            fragments: vec![None; header.data_count],
+            parity: vec![None; header.data_count.div_ceil(header.group_size)],
+        }
+    }
+
+    // Restituisce None se il frammento non è coerente con quelli già ricevuti.
+    fn insert(&mut self, header: &FragmentHeader, payload: &[u8]) -> Option<()> {
+        if self.total_len != header.total_len || self.group_size != header.group_size {
+            return None;
+        }
+        if header.kind == KIND_DATA || header.kind == KIND_CONTROL_DATA {
+            if header.index >= self.fragments.len() || payload.len() != self.fragment_len(header.index) {
+                return None;
+            }
+            self.fragments[header.index] = Some(payload.to_vec());
+        } else {
+            if header.index >= self.parity.len() || payload.len() != FRAGMENT_SIZE {
+                return None;
+            }
+            self.parity[header.index] = Some(payload.to_vec());
+        }
+        Some(())
+    }
+
+    fn fragment_len(&self, index: usize) -> usize {
+        FRAGMENT_SIZE.min(self.total_len - index * FRAGMENT_SIZE)
+    }
+
+    // Ricostruisce con la parità i gruppi a cui manca un solo frammento; restituisce il
+    // pacchetto completo solo se tutti i frammenti di dati sono disponibili.
+    fn try_complete(&mut self) -> Option<Vec<u8>> {
+        let data_count = self.fragments.len();
+        for group in 0..self.parity.len() {
+            let range = group * self.group_size..((group + 1) * self.group_size).min(data_count);
+            let missing: Vec<usize> = range.clone().filter(|&i| self.fragments[i].is_none()).collect();
+            match (missing.as_slice(), &self.parity[group]) {
+                ([], _) => {}
+                ([lost], Some(parity)) => {
+                    let mut recovered = parity.clone();
+                    for i in range.filter(|i| i != lost) {
+                        if let Some(fragment) = &self.fragments[i] {
+                            recovered.iter_mut().zip(fragment.iter()).for_each(|(r, b)| *r ^= b);
+                        }
+                    }
+                    recovered.truncate(self.fragment_len(*lost));
+                    self.fragments[*lost] = Some(recovered);
+                }
+                _ => return None,
+            }
+        }
+
+        let mut packet = Vec::with_capacity(self.total_len);
+        for fragment in self.fragments.iter().flatten() {
+            packet.extend_from_slice(fragment);
+        }
+        Some(packet)
+    }
+}
+
+// Riassembla i pacchetti ricevuti via UDP. I frame incompleti più vecchi dell'ultimo
+// consegnato vengono scartati invece di bloccare quelli successivi; i pacchetti di controllo
+// restano in attesa finché non arriva una copia di ogni frammento.
+pub struct Reassembler {
+    frames: HashMap<u32, PartialFrame>,
+    last_delivered: Option<u32>,
+    // None per i pacchetti di controllo già consegnati, così le copie successive vengono ignorate
+    controls: HashMap<u32, Option<PartialFrame>>,
+    oldest_control: Option<u32>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self { frames: HashMap::new(), last_delivered: None, controls: HashMap::new(), oldest_control: None }
+    }
+
+    pub fn push(&mut self, datagram: &[u8]) -> Option<Vec<u8>> {
+        let header = FragmentHeader::parse(datagram)?;
+        let control = match header.kind {
+            KIND_DATA | KIND_PARITY => false,
+            KIND_CONTROL_DATA | KIND_CONTROL_PARITY => true,
+            _ => return None,
+        };
+        if header.total_len == 0
+            || header.total_len > MAX_PACKET_SIZE
+            || header.group_size == 0
+            || header.data_count != header.total_len.div_ceil(FRAGMENT_SIZE)
+        {
+            return None;
+        }
+        let payload = &datagram[HEADER_SIZE..HEADER_SIZE + header.payload_len];
+        if control {
+            return self.push_control(&header, payload);
+        }
+        if let Some(last) = self.last_delivered {
+            if !is_newer(header.frame_id, last) {
+                return None;
+            }
+        }
+
+        let frame = self.frames.entry(header.frame_id).or_insert_with(|| PartialFrame::new(&header));
+        frame.insert(&header, payload)?;
+
+        let completed = frame.try_complete();
+        match completed {
+            Some(packet) => {
+                let frame_id = header.frame_id;
+                self.last_delivered = Some(frame_id);
+                self.frames.retain(|&id, _| is_newer(id, frame_id));
+                Some(packet)
+            }
+            None => {
+                if self.frames.len() > MAX_PENDING_FRAMES {
+                    if let Some(oldest) = oldest(self.frames.keys().copied()) {
+                        self.frames.remove(&oldest);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn push_control(&mut self, header: &FragmentHeader, payload: &[u8]) -> Option<Vec<u8>> {
+        if self.oldest_control.is_some_and(|oldest| !is_newer(header.frame_id, oldest)) {
+            return None;
+        }
+        let entry = self.controls.entry(header.frame_id).or_insert_with(|| Some(PartialFrame::new(header)));
+        let partial = entry.as_mut()?;
+        partial.insert(header, payload)?;
+        let completed = partial.try_complete();
+        if completed.is_some() {
+            *entry = None;
+        }
+        // Oltre il limite si dimentica il più vecchio, e con lui le sue copie ancora in viaggio
+        while self.controls.len() > MAX_TRACKED_CONTROLS {
+            let Some(oldest) = oldest(self.controls.keys().copied()) else { break };
+            self.controls.remove(&oldest);
+            self.oldest_control = Some(oldest);
+        }
+        completed
+    }
+}
+
+fn oldest(ids: impl Iterator<Item = u32>) -> Option<u32> {
+    ids.min_by(|&a, &b| if is_newer(a, b) { std::cmp::Ordering::Greater } else { std::cmp::Ordering::Less })
+}
+
+fn is_newer(id: u32, reference: u32) -> bool {
+    (id.wrapping_sub(reference) as i32) > 0
+}
+
+// Generatore xorshift usato solo per simulare la perdita di pacchetti durante i test.
+struct LossSimulator {
+    probability: f32,
+    state: u64,
+}
+
+impl LossSimulator {
+    fn new(probability: f32) -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(1);
+        if probability > 0.0 {
+            println!("Perdita simulata dei datagrammi UDP: {:.1}% (seed {})", probability * 100.0, seed);
+        }
+        Self::with_seed(probability, seed)
+    }
+
+    fn with_seed(probability: f32, seed: u64) -> Self {
+        Self { probability, state: seed | 1 }
+    }
+
+    fn should_drop(&mut self) -> bool {
+        if self.probability <= 0.0 {
+            return false;
+        }
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        ((self.state >> 40) as f32 / (1u64 << 24) as f32) < self.probability
+    }
+}
+
+pub async fn start_udp_server(
+    addr: &str,
+    sender: Arc<broadcast::Sender<Arc<[u8]>>>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+    simulated_loss: f32,
+) -> io::Result<()> {
+    serve(addr, sender, stop_signal, terminate, LossSimulator::new(simulated_loss)).await
+}
+
+// Il simulatore è un parametro perché i test possano usare un seed fisso e riprodurre le perdite.
+async fn serve(
+    addr: &str,
+    sender: Arc<broadcast::Sender<Arc<[u8]>>>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+    mut loss: LossSimulator,
+) -> io::Result<()> {
+    let socket = UdpSocket::bind(addr).await?;
+    println!("Trasporto UDP disponibile su {}", addr);
+
+    let mut rx = sender.subscribe();
+    tokio::spawn(async move {
+        let mut clients: HashMap<SocketAddr, Instant> = HashMap::new();
+        let mut frame_id: u32 = 0;
+        let mut control_id: u32 = 0;
+        let mut last_frame: Option<Arc<[u8]>> = None;
+        let mut buf = [0u8; HEADER_SIZE];
+
+        while !stop_signal.load(Ordering::SeqCst) && !terminate.load(Ordering::SeqCst) {
+            tokio::select! {
+                received = socket.recv_from(&mut buf) => {
+                    if let Ok((len, peer)) = received {
+                        match FragmentHeader::parse(&buf[..len]).map(|h| h.kind) {
+                            Some(KIND_HELLO) => {
+                                let is_new = clients.insert(peer, Instant::now()).is_none();
+                                let _ = socket.send_to(&control_datagram(KIND_ACK), peer).await;
+                                if is_new {
+                                    println!("Nuova connessione UDP da: {}", peer);
+                                    // Con lo schermo fermo il caster invia solo keep-alive: il nuovo client riceve subito l'ultimo frame
+                                    if let Some(ref packet) = last_frame {
+                                        for datagram in fragment(frame_id, packet, false) {
+                                            let _ = socket.send_to(&datagram, peer).await;
+                                        }
+                                        frame_id = frame_id.wrapping_add(1);
+                                    }
+                                }
+                            }
+                            Some(KIND_BYE) if clients.remove(&peer).is_some() => {
+                                println!("Connessione UDP chiusa con {}", peer);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                packet = rx.recv() => match packet {
+                    Ok(packet) => {
+                        let is_frame = matches!(protocol::parse_packet(&packet), protocol::Packet::Frame(_));
+                        if is_frame {
+                            last_frame = Some(packet.clone());
+                        }
+                        clients.retain(|peer, last_seen| {
+                            let alive = last_seen.elapsed() < CLIENT_TIMEOUT;
+                            if !alive {
+                                println!("Connessione UDP scaduta con {}", peer);
+                            }
+                            alive
+                        });
+                        if clients.is_empty() {
+                            continue;
+                        }
+                        let (datagrams, copies) = if is_frame {
+                            let datagrams = fragment(frame_id, &packet, false);
+                            frame_id = frame_id.wrapping_add(1);
+                            (datagrams, 1)
+                        } else {
+                            let datagrams = fragment(control_id, &packet, true);
+                            control_id = control_id.wrapping_add(1);
+                            (datagrams, CONTROL_COPIES)
+                        };
+                        for _ in 0..copies {
+                            for datagram in &datagrams {
+                                for peer in clients.keys() {
+                                    if loss.should_drop() {
+                                        continue;
+                                    }
+                                    if let Err(e) = socket.send_to(datagram, peer).await {
+                                        eprintln!("Errore nell'invio del datagramma a {}: {}", peer, e);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        eprintln!("Avviso: trasporto UDP in ritardo, saltati {} frame", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+        println!("Trasporto UDP interrotto.");
+    });
+
+    Ok(())
+}
+
+// Si registra presso il caster e restituisce i pacchetti riassemblati, nello stesso formato del trasporto TCP.
+pub async fn connect(addr: &str, stop_signal: Arc<AtomicBool>) -> io::Result<mpsc::Receiver<io::Result<Vec<u8>>>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(addr).await?;
+    socket.send(&control_datagram(KIND_HELLO)).await?;
+
+    let mut buf = vec![0u8; HEADER_SIZE + FRAGMENT_SIZE];
+    match timeout(Duration::from_secs(2), socket.recv(&mut buf)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => return Err(e),
+        Err(_) => {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Nessuna risposta dal caster UDP, controlla l'indirizzo IP inserito e riprova."));
+        }
+    }
+
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(async move {
+        let mut reassembler = Reassembler::new();
+        let mut hello = interval(HELLO_INTERVAL);
+        while !stop_signal.load(Ordering::SeqCst) {
+            tokio::select! {
+                received = socket.recv(&mut buf) => match received {
+                    Ok(len) => {
+                        if let Some(packet) = reassembler.push(&buf[..len]) {
+                            if tx.send(Ok(packet)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(e)).await;
+                        break;
+                    }
+                },
+                _ = hello.tick() => {
+                    if tx.is_closed() {
+                        break;
+                    }
+                    let _ = socket.send(&control_datagram(KIND_HELLO)).await;
+                }
+            }
+        }
+        let _ = socket.send(&control_datagram(KIND_BYE)).await;
+    });
+
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_packet(seed: u8, len: usize) -> Vec<u8> {
+        let mut packet = Vec::new();
+        protocol::begin_frame(&mut packet);
+        packet.extend((0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)));
+        protocol::finish_frame(&mut packet);
+        packet
+    }
+
+    async fn loopback_pair() -> (UdpSocket, UdpSocket) {
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.connect(receiver.local_addr().unwrap()).await.unwrap();
+        (sender, receiver)
+    }
+
+    // Invia i datagrammi sulla loopback e restituisce i pacchetti riassemblati dal receiver
+    async fn transfer(datagrams: &[&Vec<u8>], sender: &UdpSocket, receiver: &UdpSocket, reassembler: &mut Reassembler) -> Vec<Vec<u8>> {
+        let mut delivered = Vec::new();
+        let mut buf = vec![0u8; HEADER_SIZE + FRAGMENT_SIZE];
+        for datagram in datagrams {
+            sender.send(datagram).await.unwrap();
+            let len = timeout(Duration::from_secs(1), receiver.recv(&mut buf)).await.unwrap().unwrap();
+            delivered.extend(reassembler.push(&buf[..len]));
+        }
+        delivered
+    }
+
+    #[tokio::test]
+    async fn parity_recovers_one_lost_fragment_per_group() {
+        let (sender, receiver) = loopback_pair().await;
+        let mut reassembler = Reassembler::new();
+        let packet = frame_packet(1, 20 * FRAGMENT_SIZE + 300);
+        let datagrams = fragment(0, &packet, false);
+        // Si perde un frammento di dati in ogni gruppo
+        let kept: Vec<&Vec<u8>> = datagrams.iter().enumerate()
+            .filter(|(i, _)| i % (FEC_GROUP_SIZE + 1) != 2)
+            .map(|(_, d)| d)
+            .collect();
+        assert_eq!(transfer(&kept, &sender, &receiver, &mut reassembler).await, vec![packet]);
+    }
+
+    #[tokio::test]
+    async fn incomplete_frame_is_dropped_when_a_newer_one_completes() {
+        let (sender, receiver) = loopback_pair().await;
+        let mut reassembler = Reassembler::new();
+        let old = frame_packet(1, 10 * FRAGMENT_SIZE);
+        let new = frame_packet(2, 10 * FRAGMENT_SIZE);
+        let old_datagrams = fragment(0, &old, false);
+        // Due frammenti persi nello stesso gruppo non si possono ricostruire
+        let (lost, kept): (Vec<_>, Vec<_>) = old_datagrams.iter().enumerate().partition(|(i, _)| *i == 1 || *i == 2);
+        let kept: Vec<&Vec<u8>> = kept.into_iter().map(|(_, d)| d).collect();
+        assert!(transfer(&kept, &sender, &receiver, &mut reassembler).await.is_empty());
+
+        let new_datagrams = fragment(1, &new, false);
+        let all: Vec<&Vec<u8>> = new_datagrams.iter().collect();
+        assert_eq!(transfer(&all, &sender, &receiver, &mut reassembler).await, vec![new]);
+
+        // I frammenti del frame vecchio arrivati in ritardo vengono ignorati
+        let lost: Vec<&Vec<u8>> = lost.into_iter().map(|(_, d)| d).collect();
+        assert!(transfer(&lost, &sender, &receiver, &mut reassembler).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn control_packets_survive_newer_frames() {
+        let (sender, receiver) = loopback_pair().await;
+        let mut reassembler = Reassembler::new();
+        let resolution = protocol::resolution(1920, 1080);
+        let control = fragment(0, &resolution, true);
+        // La prima copia è andata persa del tutto, intanto si completa un frame più recente
+        let frame = frame_packet(3, 3 * FRAGMENT_SIZE);
+        let frame_datagrams = fragment(7, &frame, false);
+        let all: Vec<&Vec<u8>> = frame_datagrams.iter().collect();
+        assert_eq!(transfer(&all, &sender, &receiver, &mut reassembler).await, vec![frame]);
+
+        let second_copy: Vec<&Vec<u8>> = control.iter().collect();
+        assert_eq!(transfer(&second_copy, &sender, &receiver, &mut reassembler).await, vec![resolution]);
+        // Una copia in più dello stesso pacchetto non viene consegnata di nuovo
+        assert!(transfer(&second_copy, &sender, &receiver, &mut reassembler).await.is_empty());
+    }
+
+    #[test]
+    fn loss_simulator_drops_roughly_the_requested_share() {
+        let mut loss = LossSimulator::with_seed(0.1, 42);
+        let dropped = (0..10_000).filter(|_| loss.should_drop()).count();
+        assert!((800..1200).contains(&dropped), "scartati {}", dropped);
+        assert!(!LossSimulator::with_seed(0.0, 42).should_drop());
+    }
+
+    #[tokio::test]
+    async fn transport_over_loopback_with_simulated_loss() {
+        let port = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let addr = format!("127.0.0.1:{}", port);
+        let (sender, _) = broadcast::channel(64);
+        let sender = Arc::new(sender);
+        let stop = Arc::new(AtomicBool::new(false));
+        // Seed fisso: i datagrammi persi sono gli stessi a ogni esecuzione
+        serve(&addr, Arc::clone(&sender), Arc::clone(&stop), Arc::new(AtomicBool::new(false)), LossSimulator::with_seed(0.03, 7)).await.unwrap();
+        let mut packets = connect(&addr, Arc::clone(&stop)).await.unwrap();
+
+        let frames: Vec<Vec<u8>> = (0..40).map(|i| frame_packet(i, 6 * FRAGMENT_SIZE + i as usize)).collect();
+        let sent = frames.clone();
+        tokio::spawn(async move {
+            for (i, frame) in sent.into_iter().enumerate() {
+                if i == 20 {
+                    sender.send(protocol::resolution(1280, 720).into()).unwrap();
+                }
+                sender.send(frame.into()).unwrap();
+                tokio::time::sleep(Duration::from_millis(2)).await;
+            }
+            sender.send(protocol::end_of_stream().into()).unwrap();
+        });
+
+        // I frame persi possono mancare, ma quelli consegnati sono integri e in ordine;
+        // i pacchetti di controllo arrivano comunque
+        let mut last_frame = None;
+        let mut resolution_received = false;
+        loop {
+            let packet = timeout(Duration::from_secs(5), packets.recv()).await.unwrap().unwrap().unwrap();
+            match protocol::parse_packet(&packet) {
+                protocol::Packet::Frame(_) => {
+                    let index = frames.iter().position(|f| *f == packet).expect("frame corrotto");
+                    assert!(last_frame.is_none_or(|last| index > last));
+                    last_frame = Some(index);
+                }
+                protocol::Packet::Resolution(1280, 720) => resolution_received = true,
+                protocol::Packet::EndOfStream => break,
+                _ => {}
+            }
+        }
+        assert!(resolution_received);
+        assert!(last_frame.is_some());
+        stop.store(true, Ordering::SeqCst);
+    }
+}
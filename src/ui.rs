@@ -1,13 +1,21 @@
 use eframe::{egui, App, Frame};
-use crate::{caster, receiver};
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}, RwLock};
+use crate::{caster, receiver, scheduler, screen, selection};
+use crate::selection::{AspectLock, PreviewMapping};
+use crate::screen::ScreenCapturer;
+use crate::annotations::{self, Annotation, AnnotationStyle};
+use crate::exclusion::ScreenRect;
+use crate::scaling::OutputScale;
+use crate::masks::{self, MaskMode, PrivacyMask};
+use crate::presets::{self, RegionPreset, PRESET_HOTKEYS};
+use crate::window_share::{self, WindowInfo};
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU32, Ordering}, RwLock};
 use eframe::egui::{Rect, Pos2, Color32, UiBuilder, Image, Widget, FontId};
 use tokio::runtime::Runtime;
 use image::{ImageBuffer, Rgba};
-use scrap::{Capturer, Display};
 use std::time::Duration;
 use std::thread;
-use crate::receiver::{ReceiverState, SharedFrame};
+use std::sync::mpsc;
+use crate::receiver::{ReceiverState, SharedFrame, Transport};
 
 #[derive(Debug, Clone)]
 enum Modality {
@@ -22,6 +30,11 @@ enum AnnotationTool {
     Rectangle,
     Arrow,
     Text,
+    Pen,
+    Highlighter,
+    Ellipse,
+    Line,
+    Marker,
 }
 
 
@@ -37,9 +50,11 @@ struct AnnotationState {
     active_tool: AnnotationTool,
     start_pos: Option<Pos2>,
     end_pos: Option<Pos2>,
-    annotations: Vec<Annotation>,
     editing_text: Option<String>,
     text_edit_id: Option<egui::Id>,
+    style: AnnotationStyle,
+    // Punti del tratto a mano libera in corso, in punti della finestra
+    stroke_points: Vec<Pos2>,
 }
 
 impl Default for AnnotationState {
@@ -48,35 +63,14 @@ impl Default for AnnotationState {
             active_tool: AnnotationTool::None,
             start_pos: None,
             end_pos: None,
-            annotations: Vec::new(),
             editing_text: None,
             text_edit_id: None,
+            style: annotations::load_style(),
+            stroke_points: Vec::new(),
         }
     }
 }
 
-
-pub enum Annotation {
-    #[allow(dead_code)]
-    Rectangle {
-        rect: egui::Rect,
-        color: Color32,
-    },
-    #[allow(dead_code)]
-    Arrow {
-        start: Pos2,
-        end: Pos2,
-        color: Color32,
-    },
-    #[allow(dead_code)]
-    Text {
-        pos: Pos2,
-        content: String,
-        is_editing: bool,
-        color: Color32,
-    },
-}
-
 pub struct MyApp {
     mode: Option<Modality>,
     caster_address: String,
@@ -92,16 +86,45 @@ pub struct MyApp {
     is_error: Arc<AtomicBool>,
     available_displays: Vec<DisplayInfo>,
     selected_display_index: Option<usize>,
-    start_pos_relative: Option<Pos2>,
+    aspect_lock: AspectLock,
+    even_dimensions: bool,
     shared_frame: Arc<RwLock<SharedFrame>>,
     stream_texture: Option<egui::TextureHandle>,
     receiver_state: Arc<RwLock<ReceiverState>>,
     annotation_state: AnnotationState,
+    annotations: Arc<RwLock<Vec<Annotation>>>,
+    vector_annotations: Arc<AtomicBool>,
     toolbar_visible: bool,
     paused: Arc<AtomicBool>,
     screen_blanked: Arc<AtomicBool>,
     terminate: Arc<AtomicBool>,
     connected_to_caster: Arc<AtomicBool>,
+    web_enabled: bool,
+    web_address: String,
+    rtsp_enabled: bool,
+    rtsp_address: String,
+    udp_enabled: bool,
+    quic_enabled: bool,
+    quic_address: String,
+    target_fps: Arc<AtomicU32>,
+    output_scale: Arc<RwLock<OutputScale>>,
+    show_cursor: Arc<AtomicBool>,
+    highlight_clicks: Arc<AtomicBool>,
+    capture_control: Option<mpsc::Sender<caster::CaptureCommand>>,
+    follow_enabled: bool,
+    follow_width: usize,
+    follow_height: usize,
+    privacy_masks: Arc<RwLock<Vec<PrivacyMask>>>,
+    region_presets: Arc<RwLock<Vec<RegionPreset>>>,
+    available_windows: Vec<WindowInfo>,
+    shared_window: Option<u32>,
+    exclude_own_windows: bool,
+    excluded_windows: Arc<RwLock<Vec<ScreenRect>>>,
+    preset_name: String,
+    preset_events: Option<mpsc::Receiver<usize>>,
+    mask_mode: MaskMode,
+    selecting_mask: bool,
+    transport: Transport,
 }
 #[derive(Clone)]
 #[allow(dead_code)]
@@ -129,36 +152,353 @@ impl Default for MyApp {
             is_error: Arc::new(AtomicBool::new(false)),
             available_displays: Vec::new(),
             selected_display_index: None,
-            start_pos_relative: None,
+            aspect_lock: AspectLock::default(),
+            even_dimensions: false,
             shared_frame: Arc::new(RwLock::new(SharedFrame::default())),
             stream_texture: None,
             receiver_state: Arc::new(RwLock::new(ReceiverState::new())),
             annotation_state: AnnotationState::default(),
+            annotations: Arc::new(RwLock::new(Vec::new())),
+            vector_annotations: Arc::new(AtomicBool::new(false)),
             toolbar_visible: false,
             paused: Arc::new(AtomicBool::new(false)),
             screen_blanked: Arc::new(AtomicBool::new(false)),
             terminate: Arc::new(AtomicBool::new(false)),
-            connected_to_caster: Arc::new(AtomicBool::new(false))
+            connected_to_caster: Arc::new(AtomicBool::new(false)),
+            web_enabled: false,
+            web_address: String::from("0.0.0.0:8081"),
+            rtsp_enabled: false,
+            rtsp_address: String::from("0.0.0.0:8554"),
+            udp_enabled: false,
+            quic_enabled: false,
+            quic_address: String::from("0.0.0.0:12346"),
+            target_fps: Arc::new(AtomicU32::new(scheduler::DEFAULT_FPS)),
+            output_scale: Arc::new(RwLock::new(OutputScale::Native)),
+            show_cursor: Arc::new(AtomicBool::new(true)),
+            highlight_clicks: Arc::new(AtomicBool::new(false)),
+            capture_control: None,
+            follow_enabled: false,
+            follow_width: 1280,
+            follow_height: 720,
+            privacy_masks: Arc::new(RwLock::new(masks::load_masks())),
+            region_presets: Arc::new(RwLock::new(presets::load_presets())),
+            available_windows: Vec::new(),
+            shared_window: None,
+            exclude_own_windows: true,
+            excluded_windows: Arc::new(RwLock::new(Vec::new())),
+            preset_name: String::new(),
+            preset_events: None,
+            mask_mode: MaskMode::default(),
+            selecting_mask: false,
+            transport: Transport::Tcp,
         }
     }
 }
 
 impl MyApp {
 
+    // Durante la trasmissione le modifiche a display e area vengono inoltrate al caster.
+    fn send_capture_command(&self, command: caster::CaptureCommand) {
+        if !self.caster_running.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(control) = &self.capture_control {
+            let _ = control.send(command);
+        }
+    }
+
+    fn add_privacy_mask(&mut self, display_index: usize, rect: Rect, display_size: (usize, usize)) {
+        let mask = PrivacyMask {
+            display_index,
+            min_x: rect.min.x as usize,
+            min_y: rect.min.y as usize,
+            max_x: rect.max.x as usize,
+            max_y: rect.max.y as usize,
+            mode: self.mask_mode,
+            reference: Some(display_size),
+        };
+        self.privacy_masks.write().unwrap().push(mask);
+        self.save_privacy_masks();
+    }
+
+    fn save_privacy_masks(&self) {
+        if let Err(e) = masks::save_masks(&self.privacy_masks.read().unwrap()) {
+            self.set_error(format!("Errore nel salvataggio delle maschere privacy: {}", e));
+        }
+    }
+
+    // Modifica numerica dell'area in pixel fisici, con proporzioni bloccate e dimensioni pari.
+    fn show_area_editor(&mut self, ui: &mut egui::Ui) {
+        let mut constraints_changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Proporzioni:");
+            egui::ComboBox::from_id_salt("aspect_lock")
+                .selected_text(self.aspect_lock.label())
+                .show_ui(ui, |ui| {
+                    for lock in [AspectLock::Free, AspectLock::Wide, AspectLock::Standard] {
+                        constraints_changed |= ui.selectable_value(&mut self.aspect_lock, lock, lock.label()).changed();
+                    }
+                });
+            constraints_changed |= ui.checkbox(&mut self.even_dimensions, "Dimensioni pari")
+                .on_hover_text("Larghezza e altezza multiple di 2, richieste da molti codec video")
+                .changed();
+        });
+
+        let display = self.selected_display_index.and_then(|index| self.available_displays.get(index));
+        let (Some(area), Some(display)) = (self.selected_area, display) else {
+            return;
+        };
+        let display_size = (display.width, display.height);
+        let (mut x, mut y, mut width, mut height) = selection::area_to_xywh(area);
+        let mut width_changed = false;
+        let mut height_changed = false;
+        let mut position_changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Area selezionata:");
+            position_changed |= ui.add(egui::DragValue::new(&mut x).range(0..=display.width - 1).prefix("x: ")).changed();
+            position_changed |= ui.add(egui::DragValue::new(&mut y).range(0..=display.height - 1).prefix("y: ")).changed();
+            width_changed = ui.add(egui::DragValue::new(&mut width).range(1..=display.width).prefix("L: ").suffix(" px")).changed();
+            height_changed = ui.add(egui::DragValue::new(&mut height).range(1..=display.height).prefix("A: ").suffix(" px")).changed();
+        });
+        if !(constraints_changed || position_changed || width_changed || height_changed) {
+            return;
+        }
+
+        // Con le proporzioni bloccate il lato modificato determina l'altro
+        if let Some((ratio_w, ratio_h)) = self.aspect_lock.ratio() {
+            if width_changed {
+                height = width * ratio_h / ratio_w;
+            } else if height_changed {
+                width = height * ratio_w / ratio_h;
+            }
+        }
+        let edited = selection::xywh_to_area(x, y, width, height);
+        if let Some(new_area) = selection::constrain(edited, edited.min, self.aspect_lock, self.even_dimensions, display_size) {
+            if new_area != area {
+                self.selected_area = Some(new_area);
+                self.shared_window = None;
+                self.send_capture_command(caster::CaptureCommand::Area(Some(new_area)));
+            }
+        }
+    }
+
+    // Applica un preset all'interfaccia e, se richiesto, al caster in trasmissione. Quando il
+    // preset è stato attivato con il tasto rapido il caster lo ha già applicato.
+    fn apply_preset(&mut self, index: usize, notify_caster: bool) {
+        let preset = match self.region_presets.read().unwrap().get(index) {
+            Some(preset) => preset.clone(),
+            None => return,
+        };
+        let Some(position) = self.display_position(preset.display_index) else {
+            self.set_error(format!("Il display del preset \"{}\" non è disponibile", preset.name));
+            return;
+        };
+        let display_changed = self.selected_display_index != Some(position);
+        self.selected_display_index = Some(position);
+        self.selected_area = preset.area;
+        self.shared_window = None;
+        if notify_caster {
+            if display_changed {
+                self.send_capture_command(caster::CaptureCommand::Display(preset.display_index));
+            }
+            self.send_capture_command(caster::CaptureCommand::Area(preset.area));
+        }
+        self.status_message = format!("Preset \"{}\" applicato", preset.name);
+    }
+
+    // Salva display e area correnti con il nome inserito, sovrascrivendo un preset omonimo.
+    fn save_current_preset(&mut self) {
+        let Some(display_index) = self.selected_display() else {
+            return;
+        };
+        let name = self.preset_name.trim().replace(['\t', '\n'], " ");
+        if name.is_empty() {
+            return;
+        }
+        {
+            let mut region_presets = self.region_presets.write().unwrap();
+            match presets::find_preset(&region_presets, &name) {
+                Some(index) => {
+                    region_presets[index].display_index = display_index;
+                    region_presets[index].area = self.selected_area;
+                }
+                None => region_presets.push(RegionPreset { name: name.clone(), display_index, area: self.selected_area, hotkey: None }),
+            }
+        }
+        self.save_region_presets();
+        self.preset_name.clear();
+        self.status_message = format!("Preset \"{}\" salvato", name);
+    }
+
+    fn save_region_presets(&self) {
+        if let Err(e) = presets::save_presets(&self.region_presets.read().unwrap()) {
+            self.set_error(format!("Errore nel salvataggio dei preset: {}", e));
+        }
+    }
+
+    // Comunica al caster dove si trovano le finestre dell'applicazione, in pixel fisici.
+    // Con la barra degli strumenti si esclude solo la barra: il resto della finestra è trasparente
+    // e le annotazioni sono disegnate dal caster. Negli altri casi (pannello, selezione) l'intera finestra.
+    fn publish_excluded_windows(&self, ctx: &egui::Context, toolbar_rect: Option<Rect>) {
+        let (inner_rect, outer_rect, minimized, pixels_per_point) = ctx.input(|i| {
+            let viewport = i.viewport();
+            (viewport.inner_rect, viewport.outer_rect, viewport.minimized.unwrap_or(false), viewport.native_pixels_per_point.unwrap_or(1.0))
+        });
+        let window_rect = match toolbar_rect {
+            Some(toolbar_rect) => inner_rect.map(|inner| toolbar_rect.translate(inner.min.to_vec2())),
+            None if minimized => None,
+            None => outer_rect,
+        };
+        let rects = window_rect
+            .filter(|_| self.exclude_own_windows)
+            .map(|rect| ScreenRect {
+                min_x: (rect.min.x * pixels_per_point).floor() as i32,
+                min_y: (rect.min.y * pixels_per_point).floor() as i32,
+                max_x: (rect.max.x * pixels_per_point).ceil() as i32,
+                max_y: (rect.max.y * pixels_per_point).ceil() as i32,
+            });
+        *self.excluded_windows.write().unwrap() = rects.into_iter().collect();
+    }
+
+    // Condivisione di una sola finestra (X11): il caster ne segue posizione e dimensioni.
+    fn show_window_picker(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Finestra:");
+            let selected_title = self.shared_window
+                .and_then(|id| self.available_windows.iter().find(|w| w.id == id))
+                .map(|w| w.title.clone())
+                .unwrap_or_else(|| "Nessuna (area o schermo)".to_string());
+            let mut chosen = None;
+            egui::ComboBox::from_id_salt("shared_window")
+                .selected_text(selected_title)
+                .width(260.0)
+                .show_ui(ui, |ui| {
+                    if ui.selectable_label(self.shared_window.is_none(), "Nessuna (area o schermo)").clicked() {
+                        chosen = Some(None);
+                    }
+                    for window in &self.available_windows {
+                        if ui.selectable_label(self.shared_window == Some(window.id), &window.title).clicked() {
+                            chosen = Some(Some(window.id));
+                        }
+                    }
+                });
+            if ui.button("🔄").on_hover_text("Aggiorna l'elenco delle finestre").clicked() {
+                self.available_windows = window_share::list_windows();
+                if self.available_windows.is_empty() {
+                    self.status_message = "Nessuna finestra trovata (disponibile solo su X11)".to_string();
+                }
+            }
+            if let Some(window) = chosen.filter(|window| *window != self.shared_window) {
+                self.shared_window = window;
+                self.send_capture_command(caster::CaptureCommand::Window(window));
+            }
+        });
+    }
+
+    fn show_region_presets(&mut self, ui: &mut egui::Ui) {
+        let preset_count = self.region_presets.read().unwrap().len();
+        ui.collapsing(format!("Preset aree ({})", preset_count), |ui| {
+            let mut chosen = None;
+            let mut removed = None;
+            let mut hotkey_changed = None;
+            {
+                let region_presets = self.region_presets.read().unwrap();
+                egui::ComboBox::from_id_salt("region_preset")
+                    .selected_text("Scegli un preset")
+                    .show_ui(ui, |ui| {
+                        for (index, preset) in region_presets.iter().enumerate() {
+                            if ui.selectable_label(false, &preset.name).clicked() {
+                                chosen = Some(index);
+                            }
+                        }
+                    });
+                for (index, preset) in region_presets.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let area = match preset.area {
+                            Some(area) => {
+                                let (x, y, width, height) = selection::area_to_xywh(area);
+                                format!("{}x{} in ({}, {})", width, height, x, y)
+                            }
+                            None => "schermo intero".to_string(),
+                        };
+                        ui.label(format!("{}: {}, {}", preset.name, screen::display_name(preset.display_index), area));
+                        egui::ComboBox::from_id_salt(("preset_hotkey", index))
+                            .selected_text(presets::hotkey_label(preset.hotkey))
+                            .width(80.0)
+                            .show_ui(ui, |ui| {
+                                for hotkey in std::iter::once(None).chain(PRESET_HOTKEYS.iter().copied().map(Some)) {
+                                    if ui.selectable_label(preset.hotkey == hotkey, presets::hotkey_label(hotkey)).clicked() {
+                                        hotkey_changed = Some((index, hotkey));
+                                    }
+                                }
+                            })
+                            .response
+                            .on_hover_text("Tasto rapido, attivo anche con la finestra in secondo piano");
+                        if ui.small_button("🗑").on_hover_text("Rimuovi preset").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                }
+            }
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.preset_name).hint_text("Nome, es. Slide").desired_width(140.0));
+                let can_save = self.selected_display_index.is_some() && !self.preset_name.trim().is_empty();
+                if ui.add_enabled(can_save, egui::Button::new("Salva area corrente")).clicked() {
+                    self.save_current_preset();
+                }
+            });
+
+            if let Some(index) = chosen {
+                self.apply_preset(index, true);
+            }
+            if let Some((index, hotkey)) = hotkey_changed {
+                let mut region_presets = self.region_presets.write().unwrap();
+                // Un tasto appartiene a un solo preset
+                for preset in region_presets.iter_mut().filter(|preset| hotkey.is_some() && preset.hotkey == hotkey) {
+                    preset.hotkey = None;
+                }
+                region_presets[index].hotkey = hotkey;
+                drop(region_presets);
+                self.save_region_presets();
+            }
+            if let Some(index) = removed {
+                self.region_presets.write().unwrap().remove(index);
+                self.save_region_presets();
+            }
+        });
+    }
+
     fn handle_recording_error(&self, error: String) {
         self.set_error(format!("Errore di registrazione: {}", error));
     }
+    // Indice del display per il caster: la voce "Tutti i display" corrisponde a ALL_DISPLAYS.
+    fn selected_display(&self) -> Option<usize> {
+        self.selected_display_index.and_then(|position| self.available_displays.get(position)).map(|display| display.index)
+    }
+
+    fn display_position(&self, display_index: usize) -> Option<usize> {
+        self.available_displays.iter().position(|display| display.index == display_index)
+    }
+
     fn refresh_displays(&mut self) {
         self.available_displays.clear();
-        if let Ok(displays) = Display::all() {
-            for (index, display) in displays.iter().enumerate() {
-                self.available_displays.push(DisplayInfo {
-                    name: format!("Display {} ({}x{})", index + 1, display.width(), display.height()),
-                    width: display.width(),
-                    height: display.height(),
-                    index,
-                });
-            }
+        let layout = screen::display_layout();
+        for (index, display) in layout.iter().enumerate() {
+            self.available_displays.push(DisplayInfo {
+                name: format!("Display {} ({}x{})", index + 1, display.width, display.height),
+                width: display.width,
+                height: display.height,
+                index,
+            });
+        }
+        // Con più monitor si possono catturare tutti insieme, disposti come in ScreenCapturer
+        if let Some(canvas) = screen::source_geometry(&layout, screen::ALL_DISPLAYS).filter(|_| layout.len() > 1) {
+            self.available_displays.push(DisplayInfo {
+                name: format!("{} ({}x{})", screen::display_name(screen::ALL_DISPLAYS), canvas.width, canvas.height),
+                width: canvas.width,
+                height: canvas.height,
+                index: screen::ALL_DISPLAYS,
+            });
         }
         if self.available_displays.len() == 1 {
             self.selected_display_index = Some(0);
@@ -181,6 +521,21 @@ impl MyApp {
         *self.error_message.write().unwrap() = Some(error);
         self.is_error.store(true, Ordering::SeqCst);
     }
+    // Area selezionata in pixel del display tra il punto di partenza e quello corrente.
+    // Le maschere privacy non seguono i vincoli di proporzione dell'area condivisa.
+    fn selection_rect(&self, start: Pos2, current: Pos2, display_size: (usize, usize)) -> Option<Rect> {
+        let rect = Rect::from_two_pos(start, current);
+        if self.selecting_mask {
+            selection::constrain(rect, start, AspectLock::Free, false, display_size)
+        } else {
+            selection::constrain(rect, start, self.aspect_lock, self.even_dimensions, display_size)
+        }
+    }
+
+    fn screenshot_size(&self) -> Option<(usize, usize)> {
+        self.screenshot.as_ref().map(|texture| (texture.size()[0], texture.size()[1]))
+    }
+
     fn handle_selection(&mut self, ctx: &egui::Context, image_rect: egui::Rect) {
         if self.selecting_area {
             ctx.output_mut(|o| o.cursor_icon = egui::CursorIcon::Crosshair);
@@ -194,55 +549,45 @@ impl MyApp {
 
             if let (Some(current_pos), pressed, released) = response {
 
-                if image_rect.width() <= 0.0 || image_rect.height() <= 0.0 {
-                    self.selecting_area = false;
-                    self.set_error("Invalid display dimensions".to_string());
-                    return;
-                }
-
-                let clamped_pos = Pos2::new(
-                    current_pos.x.clamp(image_rect.min.x, image_rect.max.x),
-                    current_pos.y.clamp(image_rect.min.y, image_rect.max.y)
-                );
+                let display_size = match self.screenshot_size() {
+                    Some(size) if image_rect.width() > 0.0 && image_rect.height() > 0.0 => size,
+                    _ => {
+                        self.selecting_area = false;
+                        self.set_error("Invalid display dimensions".to_string());
+                        return;
+                    }
+                };
+                // Il punto viene convertito subito in pixel fisici dello screenshot: l'anteprima
+                // può cambiare posizione e scala durante il trascinamento (passaggio a schermo intero)
+                let mapping = PreviewMapping::new(image_rect, display_size);
+                let current_pixel = mapping.to_pixels(current_pos);
 
                 if pressed && self.start_pos.is_none() {
-                    if clamped_pos.x.is_finite() && clamped_pos.y.is_finite() {
-                        self.start_pos = Some(clamped_pos);
-                        self.start_pos_relative = Some(Pos2::new(
-                            (clamped_pos.x - image_rect.min.x) / image_rect.width(),
-                            (clamped_pos.y - image_rect.min.y) / image_rect.height()
-                        ));
+                    if current_pos.x.is_finite() && current_pos.y.is_finite() {
+                        self.start_pos = Some(current_pixel);
                     }
                 } else if released && self.start_pos.is_some() {
-                    if let Some(start_relative) = self.start_pos_relative {
-                        let end_relative = Pos2::new(
-                            ((clamped_pos.x - image_rect.min.x) / image_rect.width()).clamp(0.0, 1.0),
-                            ((clamped_pos.y - image_rect.min.y) / image_rect.height()).clamp(0.0, 1.0)
-                        );
-                        if let Some(display_index) = self.selected_display_index {
-                            if let Ok(displays) = Display::all() {
-                                if let Some(display) = displays.get(display_index) {
-                                    let screen_width = display.width() as f32;
-                                    let screen_height = display.height() as f32;
-                                    let min_x = (start_relative.x.min(end_relative.x) * screen_width).round();
-                                    let min_y = (start_relative.y.min(end_relative.y) * screen_height).round();
-                                    let max_x = (start_relative.x.max(end_relative.x) * screen_width).round();
-                                    let max_y = (start_relative.y.max(end_relative.y) * screen_height).round();
-                                    if min_x < max_x && min_y < max_y {
-                                        self.selected_area = Some(Rect::from_min_max(
-                                            Pos2::new(min_x, min_y),
-                                            Pos2::new(max_x, max_y)
-                                        ));
-                                    }
-                                }
+                    if let Some(start) = self.start_pos {
+                        let rect = self.selection_rect(start, current_pixel, display_size);
+                        if let (Some(display_index), Some(rect)) = (self.selected_display(), rect) {
+                            if self.selecting_mask {
+                                self.add_privacy_mask(display_index, rect, display_size);
+                            } else {
+                                self.selected_area = Some(rect);
+                                self.shared_window = None;
                             }
                         }
 
                         self.selecting_area = false;
                         ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(false));
 
-                        if let Some(area) = self.selected_area {
+                        if self.selecting_mask {
+                            // La stessa selezione a trascinamento è usata per le maschere privacy
+                            self.selecting_mask = false;
+                            self.status_message = "Maschera privacy aggiunta".to_string();
+                        } else if let (Some(area), Some(_)) = (self.selected_area, rect) {
                             self.status_message = format!("Area selezionata: {:?}", area);
+                            self.send_capture_command(caster::CaptureCommand::Area(Some(area)));
                         } else {
                             self.status_message = "Selezione area non valida".to_string();
                         }
@@ -258,70 +603,54 @@ impl MyApp {
         }
     }
 
-    fn draw_arrow(painter: &egui::Painter, start: egui::Pos2, end: egui::Pos2, color: egui::Color32) {
-        let stroke = egui::Stroke::new(2.0, color);
-
-        let dir = end - start;
-        let length = dir.length();
-        if length < 5.0 {
-            return;
+    fn draw_arrow(painter: &egui::Painter, start: egui::Pos2, end: egui::Pos2, color: egui::Color32, width: f32) {
+        if let Some((base, head)) = annotations::arrow_geometry(start, end, width) {
+            let stroke = egui::Stroke::new(width, color);
+            painter.line_segment([start, base], stroke);
+            painter.add(egui::Shape::convex_polygon(head.to_vec(), color, stroke));
         }
-
-        let dir_normalized = dir / length;
-
-        let arrowhead_length = 16.0;
-        let arrowhead_width = 10.0;
-        let tip = end;
-        let base = end - dir_normalized * arrowhead_length;
-
-        let perp = egui::Vec2::new(-dir_normalized.y, dir_normalized.x) * (arrowhead_width / 2.0);
-        let left = base + perp;
-        let right = base - perp;
-
-        let line_end = base;
-        painter.line_segment([start, line_end], stroke);
-
-        let points = vec![tip, left, right];
-        painter.add(egui::Shape::convex_polygon(points, color, stroke));
     }
 
     fn capture_screenshot(&mut self, ctx: &egui::Context) {
-        let display_index = match self.selected_display_index {
+        let display_index = match self.selected_display() {
             Some(index) => index,
             None => {
                 self.set_error("Nessun display selezionato".to_string());
                 return;
             }
         };
-        let displays = match Display::all() {
-            Ok(displays) => {
-                displays },
-            Err(e) => {
-                self.set_error(format!("Errore nell'accesso ai display: {}", e));
-                return;
-            }
-        };
-
-        if display_index >= displays.len() {
-            self.set_error("Indice del display non valido".to_string());
-            return;
-        }
-
-        let target_display = displays.into_iter().nth(display_index).unwrap();
-        let width= target_display.width();
-        let height =target_display.height();
-        let mut capturer = match Capturer::new(target_display) {
-            Ok(capturer) => {
-                capturer },
+        // Stessa sorgente del caster: con "Tutti i display" lo screenshot è il canvas affiancato
+        let mut screen = match ScreenCapturer::open(display_index) {
+            Ok(screen) => {
+                screen },
             Err(e) => {
                 self.set_error(format!("Errore nella creazione del capturer: {}", e));
                 return;
             }
         };
-        let frame = loop {
-            match capturer.frame() {
-                Ok(frame) => {
-                    break frame; },
+        let width = screen.width();
+        let height = screen.height();
+
+        let mut img_buffer: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(
+           width as u32,
+           height as u32
+        );
+        loop {
+            let captured = screen.with_frame(|frame, stride| {
+                for y in 0..height {
+                    for x in 0..width {
+                        let idx = y * stride + x * 4;
+                        if idx + 3 < frame.len() {
+                            let b = frame[idx];
+                            let g = frame[idx + 1];
+                            let r = frame[idx + 2];
+                            img_buffer.put_pixel(x as u32, y as u32, Rgba([r, g, b, 255]));
+                        }
+                    }
+                }
+            });
+            match captured {
+                Ok(()) => break,
                 Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
                     thread::sleep(Duration::from_millis(100));
                     continue;
@@ -331,24 +660,6 @@ impl MyApp {
                     return;
                 }
             }
-        };
-        let stride = frame.len() / height;
-
-        let mut img_buffer: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(
-           width as u32,
-           height as u32
-        );
-
-        for y in 0..height {
-            for x in 0..width {
-                let idx = y * stride + x * 4;
-                if idx + 3 < frame.len() {
-                    let b = frame[idx];
-                    let g = frame[idx + 1];
-                    let r = frame[idx + 2];
-                    img_buffer.put_pixel(x as u32, y as u32, Rgba([r, g, b, 255]));
-                }
-            }
         }
 
         let color_image = egui::ColorImage::from_rgba_unmultiplied(
@@ -365,44 +676,127 @@ impl MyApp {
         ctx.send_viewport_cmd(egui::ViewportCommand::Transparent(false));
     }
     fn show_annotation_toolbar(&mut self, ui: &mut egui::Ui) {
-        ui.horizontal(|ui| {
-            let mut tool_button = |ui: &mut egui::Ui, tool: AnnotationTool, icon: &str ,label: &str| {
-                let button = egui::Button::new(format!("{icon} {label}"))
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                let mut tool_button = |ui: &mut egui::Ui, tool: AnnotationTool, icon: &str ,label: &str| {
+                    let button = egui::Button::new(format!("{icon} {label}"))
+                        .min_size(egui::vec2(40.0, 20.0));
+                    let response = ui.add(button);
+
+                    if response.clicked(){
+                        self.annotation_state.active_tool = tool;
+                    }
+
+                    if self.annotation_state.active_tool == tool{
+                        response.clone().highlight();
+                    }
+                    response.on_hover_text(format!("Usa lo strumento: {label}"));
+                };
+
+                tool_button(ui, AnnotationTool::Rectangle, "▭", "Rettangolo");
+                tool_button(ui, AnnotationTool::Arrow, "➡", "Freccia");
+                tool_button(ui, AnnotationTool::Text, "📝", "Testo");
+                tool_button(ui, AnnotationTool::Pen, "✏", "Penna");
+                tool_button(ui, AnnotationTool::Highlighter, "🖌", "Evidenziatore");
+                tool_button(ui, AnnotationTool::Ellipse, "⭕", "Ellisse");
+                tool_button(ui, AnnotationTool::Line, "➖", "Linea");
+                tool_button(ui, AnnotationTool::Marker, "🔢", "Passi");
+                // Clear button
+                let clear_button = egui::Button::new("❌ Cancella Tutto")
                     .min_size(egui::vec2(40.0, 20.0));
-                let response = ui.add(button);
 
-                if response.clicked(){
-                    self.annotation_state.active_tool = tool.clone();
+                if ui.add(clear_button).on_hover_text("Elimina tutte le annotazioni").clicked(){
+                    self.annotations.write().unwrap().clear();
                 }
+            });
+            self.show_annotation_style(ui);
+        });
+    }
 
-                if self.annotation_state.active_tool == tool{
-                    response.clone().highlight();
+    // Colore, spessore e dimensione del testo delle prossime annotazioni; quelle già disegnate
+    // mantengono il proprio stile.
+    fn show_annotation_style(&mut self, ui: &mut egui::Ui) {
+        let style = &mut self.annotation_state.style;
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            for (color, name) in annotations::PALETTE {
+                let selected = style.color == color;
+                let swatch = egui::Button::new("")
+                    .fill(color)
+                    .stroke(egui::Stroke::new(if selected { 2.5 } else { 1.0 }, if selected { Color32::WHITE } else { Color32::GRAY }))
+                    .min_size(egui::vec2(18.0, 18.0));
+                if ui.add(swatch).on_hover_text(name).clicked() {
+                    style.color = color;
+                    changed = true;
                 }
-                response.on_hover_text(format!("Usa lo strumento: {label}"));
-            };
-
-            tool_button(ui, AnnotationTool::Rectangle, "▭", "Rettangolo");
-            tool_button(ui, AnnotationTool::Arrow, "➡", "Freccia");
-            tool_button(ui, AnnotationTool::Text, "📝", "Testo");
-            // Clear button
-            let clear_button = egui::Button::new("❌ Cancella Tutto")
-                .min_size(egui::vec2(40.0, 20.0));
-
-            if ui.add(clear_button).on_hover_text("Elimina tutte le annotazioni").clicked(){
-                self.annotation_state.annotations.clear();
             }
+            ui.separator();
+            changed |= ui.add(egui::Slider::new(&mut style.stroke, annotations::STROKE_RANGE).text("Spessore").step_by(0.5)).changed();
+            changed |= ui.add(egui::Slider::new(&mut style.font_size, annotations::FONT_SIZE_RANGE).text("Testo").step_by(1.0)).changed();
         });
+        if changed {
+            if let Err(e) = annotations::save_style(style) {
+                eprintln!("Errore nel salvataggio dello stile delle annotazioni: {}", e);
+            }
+        }
     }
 
 
+    // Disegna un'annotazione già riportata in punti della finestra.
+    fn paint_annotation(painter: &egui::Painter, annotation: &Annotation) {
+        match annotation {
+            Annotation::Rectangle { rect, color, width } => {
+                painter.rect_stroke(*rect, 0.0, egui::Stroke::new(*width, *color));
+            },
+            Annotation::Arrow { start, end, color, width } => {
+                Self::draw_arrow(painter, *start, *end, *color, *width);
+            },
+            Annotation::Text { pos, content, color, size } => {
+                painter.text(*pos, egui::Align2::LEFT_TOP, content, FontId::proportional(*size), *color);
+            },
+            Annotation::Pen { points, color, width } => {
+                painter.add(egui::Shape::line(points.clone(), egui::Stroke::new(*width, *color)));
+            },
+            Annotation::Highlighter { points, color, width } => {
+                painter.add(egui::Shape::line(points.clone(), egui::Stroke::new(*width, annotations::highlighter_color(*color))));
+            },
+            Annotation::Ellipse { rect, color, width } => {
+                painter.add(egui::Shape::line(annotations::ellipse_points(*rect), egui::Stroke::new(*width, *color)));
+            },
+            Annotation::Line { start, end, color, width } => {
+                painter.line_segment([*start, *end], egui::Stroke::new(*width, *color));
+            },
+            Annotation::Marker { center, number, color, radius } => {
+                painter.circle_filled(*center, *radius, *color);
+                painter.text(*center, egui::Align2::CENTER_CENTER, number.to_string(), annotations::marker_font(*radius), annotations::marker_text_color(*color));
+            },
+        }
+    }
+
+    // Posizione della finestra sullo schermo e scala: le annotazioni sono salvate in pixel fisici
+    // dello schermo, come le vede il caster, e riportate in punti della finestra per disegnarle.
+    fn overlay_mapping(ctx: &egui::Context) -> (egui::Vec2, f32) {
+        ctx.input(|i| {
+            let viewport = i.viewport();
+            (viewport.inner_rect.map(|rect| rect.min.to_vec2()).unwrap_or_default(), viewport.native_pixels_per_point.unwrap_or(1.0))
+        })
+    }
+
     fn handle_annotations(&mut self, ui: &mut egui::Ui) {
+        // Solo i clic sull'overlay disegnano: quelli sulla barra degli strumenti restano alla barra
+        let canvas = ui.interact(ui.max_rect(), ui.id().with("annotation_canvas"), egui::Sense::click_and_drag());
         let pointer_pos = ui.input(|i| i.pointer.hover_pos());
-        let mouse_pressed = ui.input(|i| i.pointer.primary_pressed());
-        let mouse_released = ui.input(|i| i.pointer.primary_released());
+        let mouse_pressed = ui.input(|i| i.pointer.primary_pressed()) && canvas.hovered();
+        let mouse_released = ui.input(|i| i.pointer.primary_released()) && (canvas.hovered() || canvas.drag_stopped());
+        let (window_offset, pixels_per_point) = Self::overlay_mapping(ui.ctx());
+        let style = self.annotation_state.style;
+        let snap = ui.input(|i| i.modifiers.shift);
+        let to_screen = |pos: Pos2| ((pos + window_offset).to_vec2() * pixels_per_point).to_pos2();
 
         if let Some(pos) = pointer_pos {
             if mouse_pressed {
                 self.annotation_state.start_pos = Some(pos);
+                self.annotation_state.stroke_points = vec![pos];
                 if self.annotation_state.active_tool == AnnotationTool::Text
                     && self.annotation_state.editing_text.is_none() {
                     self.annotation_state.editing_text = Some(String::new());
@@ -412,19 +806,21 @@ impl MyApp {
                 if let Some(start) = self.annotation_state.start_pos {
                     match self.annotation_state.active_tool {
                         AnnotationTool::Rectangle => {
-                            let rect = Rect::from_two_pos(start, pos);
-                            self.annotation_state.annotations.push(Annotation::Rectangle {
+                            let rect = Rect::from_two_pos(to_screen(start), to_screen(pos));
+                            self.annotations.write().unwrap().push(Annotation::Rectangle {
                                 rect,
-                                color: Color32::WHITE,
+                                color: style.color,
+                                width: style.stroke * pixels_per_point,
                             });
                             self.annotation_state.start_pos = None;
                             self.annotation_state.end_pos = None;
                         },
                         AnnotationTool::Arrow => {
-                            self.annotation_state.annotations.push(Annotation::Arrow {
-                                start,
-                                end: pos,
-                                color: Color32::WHITE,
+                            self.annotations.write().unwrap().push(Annotation::Arrow {
+                                start: to_screen(start),
+                                end: to_screen(pos),
+                                color: style.color,
+                                width: style.stroke * pixels_per_point,
                             });
                             self.annotation_state.start_pos = None;
                             self.annotation_state.end_pos = None;
@@ -432,9 +828,61 @@ impl MyApp {
                         },
                         AnnotationTool::Text => {
                         },
+                        AnnotationTool::Ellipse => {
+                            self.annotations.write().unwrap().push(Annotation::Ellipse {
+                                rect: Rect::from_two_pos(to_screen(start), to_screen(pos)),
+                                color: style.color,
+                                width: style.stroke * pixels_per_point,
+                            });
+                            self.annotation_state.start_pos = None;
+                        },
+                        AnnotationTool::Line => {
+                            let end = if snap { annotations::snap_angle(start, pos) } else { pos };
+                            self.annotations.write().unwrap().push(Annotation::Line {
+                                start: to_screen(start),
+                                end: to_screen(end),
+                                color: style.color,
+                                width: style.stroke * pixels_per_point,
+                            });
+                            self.annotation_state.start_pos = None;
+                        },
+                        AnnotationTool::Marker => {
+                            let mut existing = self.annotations.write().unwrap();
+                            let number = annotations::next_marker_number(&existing);
+                            existing.push(Annotation::Marker {
+                                center: to_screen(start),
+                                number,
+                                color: style.color,
+                                radius: style.font_size * annotations::MARKER_RADIUS_FACTOR * pixels_per_point,
+                            });
+                            self.annotation_state.start_pos = None;
+                        },
+                        AnnotationTool::Pen | AnnotationTool::Highlighter => {
+                            self.annotation_state.stroke_points.push(pos);
+                            let points = annotations::smooth_stroke(&self.annotation_state.stroke_points)
+                                .into_iter()
+                                .map(to_screen)
+                                .collect();
+                            let annotation = if self.annotation_state.active_tool == AnnotationTool::Pen {
+                                Annotation::Pen { points, color: style.color, width: style.stroke * pixels_per_point }
+                            } else {
+                                let width = style.stroke * annotations::HIGHLIGHTER_WIDTH_FACTOR * pixels_per_point;
+                                Annotation::Highlighter { points, color: style.color, width }
+                            };
+                            self.annotations.write().unwrap().push(annotation);
+                            self.annotation_state.stroke_points.clear();
+                            self.annotation_state.start_pos = None;
+                        },
                         _ => {}
                     }
                 }
+            } else if self.annotation_state.start_pos.is_some()
+                && matches!(self.annotation_state.active_tool, AnnotationTool::Pen | AnnotationTool::Highlighter) {
+                // Durante il tratto si tiene un punto ogni pochi pixel, l'arrotondamento fa il resto
+                let moved = self.annotation_state.stroke_points.last().is_none_or(|last| last.distance(pos) >= 2.0);
+                if moved {
+                    self.annotation_state.stroke_points.push(pos);
+                }
             }
         }
 
@@ -443,20 +891,21 @@ impl MyApp {
                 if let Some(editing_text) = &mut self.annotation_state.editing_text {
                     let text_edit = egui::TextEdit::singleline(editing_text)
                         .desired_width(200.0)
-                        .font(FontId::proportional(14.0));
+                        .font(FontId::proportional(style.font_size))
+                        .text_color(style.color);
 
                     let response = ui.put(
-                        Rect::from_min_size(start, egui::Vec2::new(200.0, 20.0)),
+                        Rect::from_min_size(start, egui::Vec2::new(200.0, style.font_size + 6.0)),
                         text_edit
                     );
 
                     if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                         if !editing_text.is_empty() {
-                            self.annotation_state.annotations.push(Annotation::Text {
-                                pos: start,
+                            self.annotations.write().unwrap().push(Annotation::Text {
+                                pos: to_screen(start),
                                 content: editing_text.clone(),
-                                is_editing: true,
-                                color: Color32::WHITE,
+                                color: style.color,
+                                size: style.font_size * pixels_per_point,
                             });
                         }
                         self.annotation_state.editing_text = None;
@@ -468,34 +917,38 @@ impl MyApp {
         }
         // Draw existing annotations
         let painter = ui.painter();
-        for annotation in &self.annotation_state.annotations {
-            match annotation {
-                Annotation::Rectangle{rect, ..} => {
-                    painter.rect_stroke(*rect, 0.0, egui::Stroke::new(2.0, Color32::WHITE));
-                },
-                Annotation::Arrow { start, end, .. } => {
-                    Self::draw_arrow(&painter, *start, *end, Color32::WHITE);
-                },
-                Annotation::Text { pos, content, .. } => {
-                    painter.text(
-                        *pos,
-                        egui::Align2::LEFT_TOP,
-                        content,
-                        FontId::proportional(14.0),
-                        Color32::WHITE,
-                    );
-                },
-            }
+        // Il primo pixel dello schermo in punti della finestra: con questo rettangolo denormalized
+        // riporta le coordinate dello schermo nella finestra, come fanno i receiver con il frame
+        let screen_pixel = Rect::from_min_size((-window_offset).to_pos2(), egui::Vec2::splat(1.0 / pixels_per_point));
+        for annotation in self.annotations.read().unwrap().iter() {
+            Self::paint_annotation(painter, &annotation.denormalized(screen_pixel));
         }
 
         if let (Some(start), Some(current_pos)) = (self.annotation_state.start_pos, pointer_pos) {
             match self.annotation_state.active_tool {
                 AnnotationTool::Rectangle => {
                     let rect = Rect::from_two_pos(start, current_pos);
-                    painter.rect_stroke(rect, 0.0, egui::Stroke::new(2.0, Color32::WHITE));
+                    painter.rect_stroke(rect, 0.0, egui::Stroke::new(style.stroke, style.color));
                 },
                 AnnotationTool::Arrow => {
-                    Self::draw_arrow(&painter, start, current_pos, Color32::WHITE);
+                    Self::draw_arrow(painter, start, current_pos, style.color, style.stroke);
+                },
+                AnnotationTool::Ellipse => {
+                    let points = annotations::ellipse_points(Rect::from_two_pos(start, current_pos));
+                    painter.add(egui::Shape::line(points, egui::Stroke::new(style.stroke, style.color)));
+                },
+                AnnotationTool::Line => {
+                    let end = if snap { annotations::snap_angle(start, current_pos) } else { current_pos };
+                    painter.line_segment([start, end], egui::Stroke::new(style.stroke, style.color));
+                },
+                AnnotationTool::Pen => {
+                    let points = self.annotation_state.stroke_points.clone();
+                    painter.add(egui::Shape::line(points, egui::Stroke::new(style.stroke, style.color)));
+                },
+                AnnotationTool::Highlighter => {
+                    let points = self.annotation_state.stroke_points.clone();
+                    let width = style.stroke * annotations::HIGHLIGHTER_WIDTH_FACTOR;
+                    painter.add(egui::Shape::line(points, egui::Stroke::new(width, annotations::highlighter_color(style.color))));
                 },
                 AnnotationTool::Text => {
                 },
@@ -533,10 +986,16 @@ impl App for MyApp {
         [0.0, 0.0, 0.0, 0.0]
     }
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
-        if self.selected_display_index==None{
+        if self.selected_display_index.is_none() {
             self.refresh_displays()
         }
+        // Preset attivati dal caster con i tasti rapidi: l'interfaccia mostra la nuova area
+        let activated: Vec<usize> = self.preset_events.as_ref().map(|events| events.try_iter().collect()).unwrap_or_default();
+        for index in activated {
+            self.apply_preset(index, false);
+        }
 
+        let mut toolbar_rect = None;
         if self.selecting_area {
             egui::CentralPanel::default()
                 .frame(egui::Frame::none().fill(Color32::TRANSPARENT))
@@ -566,16 +1025,24 @@ impl App for MyApp {
                             image.ui(ui);
                         });
 
+                        // Si disegna l'area effettivamente condivisa, già arrotondata ai pixel e vincolata
+                        let display_size = (texture_size[0], texture_size[1]);
+                        let mapping = PreviewMapping::new(image_rect, display_size);
                         if let Some(start) = self.start_pos {
                             if let Some(current) = ui.input(|i| i.pointer.hover_pos()) {
-                                let clamped_current = Pos2::new(
-                                    current.x.clamp(image_rect.min.x, image_rect.max.x),
-                                    current.y.clamp(image_rect.min.y, image_rect.max.y)
-                                );
-
-                                let rect = Rect::from_two_pos(start, clamped_current);
-                                ui.painter().rect_stroke(rect, 0.0, (2.0, Color32::WHITE));
-                                ui.painter().rect_filled(rect, 0.0, Color32::from_rgba_unmultiplied(0, 0, 0, 50));
+                                if let Some(area) = self.selection_rect(start, mapping.to_pixels(current), display_size) {
+                                    let rect = mapping.rect_to_points(area);
+                                    ui.painter().rect_stroke(rect, 0.0, (2.0, Color32::WHITE));
+                                    ui.painter().rect_filled(rect, 0.0, Color32::from_rgba_unmultiplied(0, 0, 0, 50));
+                                    let (_, _, width, height) = selection::area_to_xywh(area);
+                                    ui.painter().text(
+                                        rect.left_bottom() + egui::vec2(0.0, 4.0),
+                                        egui::Align2::LEFT_TOP,
+                                        format!("{} x {} px", width, height),
+                                        FontId::proportional(14.0),
+                                        Color32::WHITE,
+                                    );
+                                }
                             }
                         }
                     }
@@ -597,7 +1064,7 @@ impl App for MyApp {
                         self.handle_selection(ctx, image_rect);
                     }
                 });
-        } else if self.toolbar_visible==true && self.caster_running.load(Ordering::SeqCst) {
+        } else if self.toolbar_visible && self.caster_running.load(Ordering::SeqCst) {
                 self.set_fullscreen_transparent(ctx);
                 egui::CentralPanel::default()
                     .frame(egui::Frame::none()
@@ -605,8 +1072,8 @@ impl App for MyApp {
                     .show(ctx, |ui| {
                         self.handle_annotations(ui);
                     });
-                egui::Window::new("")
-                .fixed_size(egui::Vec2::new(300.0, 45.0))
+                toolbar_rect = egui::Window::new("")
+                .fixed_size(egui::Vec2::new(900.0, 70.0))
                 .collapsible(false)
                     .movable(true)
                 .title_bar(false)
@@ -624,7 +1091,7 @@ impl App for MyApp {
                             self.save_original_window_state(ctx);
                         }
                     });
-                });
+                }).map(|toolbar| toolbar.response.rect);
 
         } else {
             egui::CentralPanel::default().show(ctx, |ui| {
@@ -635,6 +1102,7 @@ impl App for MyApp {
                     if caster_button.clicked()  {
                         self.clear_error();
                         self.mode = Some(Modality::Caster);
+                        self.available_windows = window_share::list_windows();
                         self.stop_signal.store(false, Ordering::SeqCst);
                         self.selecting_area = false;
                         self.status_message = "Modalità selezionata: Caster".to_string();
@@ -660,34 +1128,30 @@ impl App for MyApp {
                             });
                             ui.horizontal(|ui| {
                                 ui.label("Seleziona Monitor:");
-                                if !self.caster_running.load(Ordering::SeqCst) {
-                                    egui::ComboBox::from_label("")
-                                        .selected_text(match self.selected_display_index {
-                                            Some(index) => &self.available_displays[index].name,
-                                            None => "Seleziona un monitor",
-                                        })
-                                        .show_ui(ui, |ui| {
-                                            for (index, display) in self.available_displays.iter().enumerate() {
-                                                let response = ui.selectable_value(
-                                                    &mut self.selected_display_index,
-                                                    Some(index),
-                                                    &display.name,
-                                                );
-
-                                                if response.clicked() {
-                                                    self.selected_area = None;
-                                                }
+                                // Il monitor si può cambiare anche durante la trasmissione
+                                let mut display_changed = None;
+                                egui::ComboBox::from_label("")
+                                    .selected_text(match self.selected_display_index {
+                                        Some(index) => &self.available_displays[index].name,
+                                        None => "Seleziona un monitor",
+                                    })
+                                    .show_ui(ui, |ui| {
+                                        for (index, display) in self.available_displays.iter().enumerate() {
+                                            let response = ui.selectable_value(
+                                                &mut self.selected_display_index,
+                                                Some(index),
+                                                &display.name,
+                                            );
+
+                                            if response.clicked() {
+                                                self.selected_area = None;
+                                                self.shared_window = None;
+                                                display_changed = Some(display.index);
                                             }
-                                        });
-                                } else {
-                                    // Mostra una versione disabilitata del combobox
-                                    ui.add_enabled(
-                                        false,
-                                        egui::Label::new(match self.selected_display_index {
-                                            Some(index) => &self.available_displays[index].name,
-                                            None => "Seleziona un monitor",
-                                        })
-                                    );
+                                        }
+                                    });
+                                if let Some(index) = display_changed {
+                                    self.send_capture_command(caster::CaptureCommand::Display(index));
                                 }
 
                                 if ui.add_enabled(!self.caster_running.load(Ordering::SeqCst), egui::Button::new("🔄")).clicked() {
@@ -696,19 +1160,86 @@ impl App for MyApp {
                                 }
                             });
 
-                            if let Some(area) = self.selected_area {
-                                ui.label(format!(
-                                    "Area selezionata: ({}, {}) - ({}, {})",
-                                    area.min.x as i32,
-                                    area.min.y as i32,
-                                    area.max.x as i32,
-                                    area.max.y as i32
-                                ));
+                            ui.horizontal(|ui| {
+                                ui.add_enabled(!self.caster_running.load(Ordering::SeqCst), egui::Checkbox::new(&mut self.web_enabled, "Server HTTP (web/MJPEG)"));
+                                let text_edit = egui::TextEdit::singleline(&mut self.web_address);
+                                ui.add_enabled(self.web_enabled && !self.caster_running.load(Ordering::SeqCst), text_edit)
+                                    .on_hover_text("Indirizzo del server HTTP per browser e lettori MJPEG, es. 0.0.0.0:8081");
+                            });
+                            ui.horizontal(|ui| {
+                                ui.add_enabled(!self.caster_running.load(Ordering::SeqCst), egui::Checkbox::new(&mut self.rtsp_enabled, "Server RTSP"));
+                                let text_edit = egui::TextEdit::singleline(&mut self.rtsp_address);
+                                ui.add_enabled(self.rtsp_enabled && !self.caster_running.load(Ordering::SeqCst), text_edit)
+                                    .on_hover_text("Indirizzo del server RTSP (RTP/JPEG), es. 0.0.0.0:8554");
+                            });
+                            ui.add_enabled(!self.caster_running.load(Ordering::SeqCst), egui::Checkbox::new(&mut self.udp_enabled, "Trasporto UDP"))
+                                .on_hover_text("Accetta anche receiver UDP sulla stessa porta del caster");
+                            ui.horizontal(|ui| {
+                                ui.add_enabled(!self.caster_running.load(Ordering::SeqCst), egui::Checkbox::new(&mut self.quic_enabled, "Trasporto QUIC"));
+                                let text_edit = egui::TextEdit::singleline(&mut self.quic_address);
+                                ui.add_enabled(self.quic_enabled && !self.caster_running.load(Ordering::SeqCst), text_edit)
+                                    .on_hover_text("Indirizzo UDP dell'endpoint QUIC, es. 0.0.0.0:12346");
+                            });
+                            // Il frame rate resta modificabile anche durante la trasmissione
+                            let mut fps = self.target_fps.load(Ordering::SeqCst);
+                            if ui.add(egui::Slider::new(&mut fps, scheduler::MIN_FPS..=scheduler::MAX_FPS).text("FPS obiettivo")).changed() {
+                                self.target_fps.store(fps, Ordering::SeqCst);
+                            }
+                            // Anche la risoluzione in uscita si può cambiare durante la trasmissione
+                            let mut scale = *self.output_scale.read().unwrap();
+                            ui.horizontal(|ui| {
+                                ui.label("Risoluzione:");
+                                egui::ComboBox::from_id_salt("output_scale")
+                                    .selected_text(scale.label())
+                                    .show_ui(ui, |ui| {
+                                        let presets = [OutputScale::Native, OutputScale::Width(1920), OutputScale::Percent(50), OutputScale::Fit(1920, 1080)];
+                                        for preset in presets {
+                                            let selected = std::mem::discriminant(&scale) == std::mem::discriminant(&preset);
+                                            if ui.selectable_label(selected, preset.label()).clicked() && !selected {
+                                                scale = preset;
+                                            }
+                                        }
+                                    });
+                                match &mut scale {
+                                    OutputScale::Native => {}
+                                    OutputScale::Width(width) => {
+                                        ui.add(egui::DragValue::new(width).range(16..=7680).suffix(" px"));
+                                    }
+                                    OutputScale::Percent(percent) => {
+                                        ui.add(egui::DragValue::new(percent).range(1..=100).suffix(" %"));
+                                    }
+                                    OutputScale::Fit(width, height) => {
+                                        ui.add(egui::DragValue::new(width).range(16..=7680));
+                                        ui.label("x");
+                                        ui.add(egui::DragValue::new(height).range(16..=4320));
+                                    }
+                                }
+                            });
+                            if scale != *self.output_scale.read().unwrap() {
+                                *self.output_scale.write().unwrap() = scale;
+                            }
+                            ui.horizontal(|ui| {
+                                let mut show_cursor = self.show_cursor.load(Ordering::SeqCst);
+                                if ui.checkbox(&mut show_cursor, "Mostra cursore").changed() {
+                                    self.show_cursor.store(show_cursor, Ordering::SeqCst);
+                                }
+                                let mut highlight_clicks = self.highlight_clicks.load(Ordering::SeqCst);
+                                if ui.add_enabled(show_cursor, egui::Checkbox::new(&mut highlight_clicks, "Evidenzia clic")).changed() {
+                                    self.highlight_clicks.store(highlight_clicks, Ordering::SeqCst);
+                                }
+                            });
+                            ui.checkbox(&mut self.exclude_own_windows, "Nascondi le finestre dell'applicazione");
+                            let mut vector_annotations = self.vector_annotations.load(Ordering::SeqCst);
+                            if ui.checkbox(&mut vector_annotations, "Annotazioni vettoriali")
+                                .on_hover_text("Inviate a parte ai receiver, che possono nasconderle; non visibili da browser e RTSP")
+                                .changed() {
+                                self.vector_annotations.store(vector_annotations, Ordering::SeqCst);
                             }
 
-                            if !self.caster_running.load(Ordering::SeqCst) {
-                                self.status_message="Modalità selezionata: Caster".to_string();
+                            self.show_area_editor(ui);
 
+                            // Anche l'area condivisa si può cambiare durante la trasmissione
+                            ui.horizontal(|ui| {
                                 let select_area_button = ui.add_enabled(
                                     self.selected_display_index.is_some(),
                                     egui::Button::new("Seleziona area")
@@ -721,6 +1252,69 @@ impl App for MyApp {
                                     self.status_message = "Clicca e trascina per selezionare l'area".to_string();
                                 }
 
+                                if ui.add_enabled(self.selected_area.is_some() || self.shared_window.is_some(), egui::Button::new("Schermo intero")).clicked() {
+                                    self.selected_area = None;
+                                    self.shared_window = None;
+                                    self.send_capture_command(caster::CaptureCommand::Area(None));
+                                }
+                            });
+                            self.show_window_picker(ui);
+                            self.show_region_presets(ui);
+                            ui.horizontal(|ui| {
+                                let mut changed = ui.checkbox(&mut self.follow_enabled, "Segui il mouse")
+                                    .on_hover_text("Condivide una finestra di dimensione fissa che segue il cursore")
+                                    .changed();
+                                changed |= ui.add_enabled(self.follow_enabled, egui::DragValue::new(&mut self.follow_width).range(160..=7680)).changed();
+                                ui.label("x");
+                                changed |= ui.add_enabled(self.follow_enabled, egui::DragValue::new(&mut self.follow_height).range(120..=4320)).changed();
+                                if changed {
+                                    let size = self.follow_enabled.then_some((self.follow_width, self.follow_height));
+                                    self.send_capture_command(caster::CaptureCommand::FollowMouse(size));
+                                }
+                            });
+                            let mask_count = self.privacy_masks.read().unwrap().len();
+                            ui.collapsing(format!("Maschere privacy ({})", mask_count), |ui| {
+                                ui.horizontal(|ui| {
+                                    egui::ComboBox::from_id_salt("mask_mode")
+                                        .selected_text(self.mask_mode.label())
+                                        .show_ui(ui, |ui| {
+                                            for mode in [MaskMode::Fill, MaskMode::Pixelate, MaskMode::Blur] {
+                                                ui.selectable_value(&mut self.mask_mode, mode, mode.label());
+                                            }
+                                        });
+                                    let add_mask_button = ui.add_enabled(
+                                        self.selected_display_index.is_some(),
+                                        egui::Button::new("Aggiungi maschera")
+                                    );
+                                    if add_mask_button.clicked() {
+                                        self.capture_screenshot(ctx);
+                                        self.selecting_area = true;
+                                        self.selecting_mask = true;
+                                        self.start_pos = None;
+                                        self.status_message = "Clicca e trascina per selezionare la zona da nascondere".to_string();
+                                    }
+                                });
+                                let mut removed = None;
+                                for (index, mask) in self.privacy_masks.read().unwrap().iter().enumerate() {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!(
+                                            "{}: ({}, {}) - ({}, {}) {}",
+                                            screen::display_name(mask.display_index), mask.min_x, mask.min_y, mask.max_x, mask.max_y, mask.mode.label()
+                                        ));
+                                        if ui.small_button("🗑").on_hover_text("Rimuovi maschera").clicked() {
+                                            removed = Some(index);
+                                        }
+                                    });
+                                }
+                                if let Some(index) = removed {
+                                    self.privacy_masks.write().unwrap().remove(index);
+                                    self.save_privacy_masks();
+                                }
+                            });
+
+                            if !self.caster_running.load(Ordering::SeqCst) {
+                                self.status_message="Modalità selezionata: Caster".to_string();
+
                                 if ui.button("Avvia").clicked() {
                                     self.clear_error();
                                     self.stream_texture = None;
@@ -744,15 +1338,41 @@ impl App for MyApp {
                                     let error_message = self.error_message.clone();
                                     let is_error = self.is_error.clone();
                                     let is_running = self.caster_running.clone(); // Assicurati di usare caster_running
-                                    let selected_display_index = self.selected_display_index.unwrap_or_else(|| 0);
+                                    let selected_display_index = self.selected_display().unwrap_or(0);
                                     let paused_clone = self.paused.clone();
                                     let screen_blanked_clone = self.screen_blanked.clone();
                                     let terminate_clone = self.terminate.clone();
                                     let connected_to_caster = self.connected_to_caster.clone();
+                                    let (control_tx, control_rx) = mpsc::channel();
+                                    self.capture_control = Some(control_tx);
+                                    let (preset_tx, preset_rx) = mpsc::channel();
+                                    self.preset_events = Some(preset_rx);
+                                    let options = caster::CasterOptions {
+                                        display_index: selected_display_index,
+                                        selected_area,
+                                        web_addr: self.web_enabled.then(|| self.web_address.clone()),
+                                        rtsp_addr: self.rtsp_enabled.then(|| self.rtsp_address.clone()),
+                                        udp_enabled: self.udp_enabled,
+                                        quic_addr: self.quic_enabled.then(|| self.quic_address.clone()),
+                                        target_fps: self.target_fps.clone(),
+                                        output_scale: self.output_scale.clone(),
+                                        show_cursor: self.show_cursor.clone(),
+                                        highlight_clicks: self.highlight_clicks.clone(),
+                                        control: Some(control_rx),
+                                        privacy_masks: self.privacy_masks.clone(),
+                                        window: self.shared_window,
+                                        excluded_windows: Some(self.excluded_windows.clone()),
+                                        annotations: self.annotations.clone(),
+                                        vector_annotations: self.vector_annotations.clone(),
+                                        region_presets: self.region_presets.clone(),
+                                        preset_activated: Some(preset_tx),
+                                        follow_mouse: self.follow_enabled.then_some((self.follow_width, self.follow_height)),
+                                        ..Default::default()
+                                    };
 
                                     std::thread::spawn(move || {
                                         Runtime::new().unwrap().block_on(async {
-                                            if let Err(e) = caster::start_caster(&caster_address, stop_signal, selected_area, selected_display_index, paused_clone, screen_blanked_clone, terminate_clone).await {
+                                            if let Err(e) = caster::start_caster(&caster_address, stop_signal, paused_clone, screen_blanked_clone, terminate_clone, options).await {
                                                 let error = format!("Errore nel caster: {}", e);
                                                 *error_message.write().unwrap() = Some(error);
                                                 is_error.store(true, Ordering::SeqCst);
@@ -783,12 +1403,18 @@ impl App for MyApp {
                                     });
 
                                     if ui.button("⏹ Stop").clicked() {
-                                        self.status_message = "Interrompendo il caster...".to_string();
                                         self.stop_signal.store(true, Ordering::SeqCst);
                                         self.caster_running.store(false, Ordering::SeqCst);
                                         self.status_message = "Caster interrotto.".to_string();
                                     }
                                 });
+                                if self.web_enabled {
+                                    ui.label(format!("Visualizzatore web: http://{}/", self.web_address));
+                                    ui.label(format!("Stream MJPEG: http://{}/stream.mjpg", self.web_address));
+                                }
+                                if self.rtsp_enabled {
+                                    ui.label(format!("Stream RTSP: rtsp://{}/", self.rtsp_address));
+                                }
                                 if self.paused.load(Ordering::SeqCst) {
                                         ui.label(
                                             egui::RichText::new("PAUSA")
@@ -815,6 +1441,18 @@ impl App for MyApp {
                                 let text_edit = egui::TextEdit::singleline(&mut self.caster_address);
                                 ui.add_enabled(!self.receiver_running.load(Ordering::SeqCst), text_edit);
                             });
+                            ui.horizontal(|ui| {
+                                ui.label("Trasporto:");
+                                ui.add_enabled_ui(!self.receiver_running.load(Ordering::SeqCst), |ui| {
+                                    egui::ComboBox::from_id_salt("transport")
+                                        .selected_text(self.transport.label())
+                                        .show_ui(ui, |ui| {
+                                            for transport in [Transport::Tcp, Transport::Udp, Transport::Quic] {
+                                                ui.selectable_value(&mut self.transport, transport, transport.label());
+                                            }
+                                        });
+                                });
+                            });
 
                             if !self.receiver_running.load(Ordering::SeqCst) {
                                 self.status_message="Modalità selezionata: Receiver".to_string();
@@ -833,10 +1471,11 @@ impl App for MyApp {
                                     let is_running = self.receiver_running.clone();
                                     let shared_frame = self.shared_frame.clone();
                                     let connected_to_caster = self.connected_to_caster.clone();
+                                    let transport = self.transport;
 
                                     std::thread::spawn(move || {
                                         Runtime::new().unwrap().block_on(async {
-                                            if let Err(e) = receiver::receive_frame(&addr, stop_signal, shared_frame,receiver_state, connected_to_caster).await {
+                                            if let Err(e) = receiver::receive_frame(&addr, transport, stop_signal, shared_frame,receiver_state, connected_to_caster).await {
                                                 let error = if e.to_string() == "Il caster ha chiuso la trasmissione." {
                                                     "Il caster ha chiuso la trasmissione.".to_string()
                                                 } else {
@@ -937,6 +1576,13 @@ impl App for MyApp {
                                     }
                                 }
 
+                                if let Ok(mut receiver_state) = self.receiver_state.write() {
+                                    ui.horizontal(|ui| {
+                                        ui.checkbox(&mut receiver_state.show_annotations, "Mostra annotazioni");
+                                        ui.checkbox(&mut receiver_state.record_annotations, "Registra annotazioni");
+                                    });
+                                }
+
                                 if let Some(texture) = &self.stream_texture {
                                     let available_size = ui.available_size();
                                     let texture_size = texture.size_vec2();
@@ -957,11 +1603,22 @@ impl App for MyApp {
                                                         .strong(),
                                                 );
                                         }
+                                        if let Some((width, height)) = receiver_state.resolution_notice() {
+                                            ui.label(format!("Risoluzione del caster cambiata: {}x{}", width, height));
+                                        }
                                     }
 
                                     let image = Image::from_texture(texture)
                                         .fit_to_exact_size(display_size);
-                                    image.ui(ui);
+                                    let image_rect = image.ui(ui).rect;
+                                    // Le annotazioni vettoriali si disegnano alla risoluzione dello schermo, sopra il video
+                                    if let Ok(receiver_state) = self.receiver_state.read() {
+                                        if receiver_state.show_annotations {
+                                            for annotation in receiver_state.annotations.values() {
+                                                Self::paint_annotation(ui.painter(), &annotation.denormalized(image_rect));
+                                            }
+                                        }
+                                    }
                                 }
                                 ctx.request_repaint();
                             }
@@ -972,5 +1629,6 @@ impl App for MyApp {
                 ui.label(&self.status_message);
             });
         }
+        self.publish_excluded_windows(ctx, toolbar_rect);
     }
 }
\ No newline at end of file
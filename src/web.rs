@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, Take};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::{sleep, Duration};
+use crate::protocol;
+
+const VIEWER_HTML: &str = include_str!("viewer.html");
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_REQUEST_HEAD: usize = 8192;
+const MJPEG_BOUNDARY: &str = "frame";
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+pub async fn start_web_server(
+    addr: &str,
+    sender: Arc<broadcast::Sender<Arc<[u8]>>>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Visualizzatore web disponibile su http://{}/", addr);
+
+    tokio::spawn(async move {
+        while !stop_signal.load(Ordering::SeqCst) && !terminate.load(Ordering::SeqCst) {
+            // Il flag di stop si ricontrolla anche senza nuovi client, così dopo Stop la porta viene liberata subito
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = sleep(STOP_CHECK_INTERVAL) => continue,
+            };
+            if let Ok((socket, peer)) = accepted {
+                let sender = Arc::clone(&sender);
+                let stop_signal_client = Arc::clone(&stop_signal);
+                let terminate_client = Arc::clone(&terminate);
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(socket, peer, sender, stop_signal_client, terminate_client).await {
+                        eprintln!("Errore nella connessione web con {}: {}", peer, e);
+                    }
+                });
+            }
+        }
+        println!("Server web interrotto.");
+    });
+
+    Ok(())
+}
+
+async fn handle_connection(
+    socket: TcpStream,
+    peer: SocketAddr,
+    sender: Arc<broadcast::Sender<Arc<[u8]>>>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    let request = read_request(&mut reader).await?;
+
+    if request.method != "GET" {
+        return write_response(&mut writer, "405 Method Not Allowed", "text/plain", b"Metodo non supportato").await;
+    }
+
+    // Ci si iscrive al canale solo per gli stream: una pagina caricata o una risorsa inesistente
+    // non devono contare come nuovi visualizzatori per il caster
+    match request.path.as_str() {
+        "/" | "/index.html" => {
+            write_response(&mut writer, "200 OK", "text/html; charset=utf-8", VIEWER_HTML.as_bytes()).await
+        }
+        "/stream.mjpg" => {
+            println!("Nuovo client MJPEG da: {}", peer);
+            stream_mjpeg(writer, sender.subscribe(), stop_signal, terminate).await?;
+            println!("Client MJPEG disconnesso: {}", peer);
+            Ok(())
+        }
+        "/ws" => {
+            let is_upgrade = request.headers.get("upgrade")
+                .map(|v| v.eq_ignore_ascii_case("websocket"))
+                .unwrap_or(false);
+            let key = match (is_upgrade, request.headers.get("sec-websocket-key")) {
+                (true, Some(key)) => key.clone(),
+                _ => return write_response(&mut writer, "400 Bad Request", "text/plain", b"Richiesta WebSocket non valida").await,
+            };
+            let handshake = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                websocket_accept_key(&key)
+            );
+            writer.write_all(handshake.as_bytes()).await?;
+            println!("Nuovo visualizzatore web da: {}", peer);
+            stream_websocket(reader, writer, sender.subscribe(), stop_signal, terminate).await?;
+            println!("Visualizzatore web disconnesso: {}", peer);
+            Ok(())
+        }
+        _ => write_response(&mut writer, "404 Not Found", "text/plain", b"Risorsa non trovata").await,
+    }
+}
+
+// La lettura è limitata a MAX_REQUEST_HEAD byte in tutto, anche per una riga senza fine.
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Request> {
+    let mut reader = reader.take(MAX_REQUEST_HEAD as u64);
+    let mut request_line = String::new();
+    read_head_line(&mut reader, &mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().split('?').next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        read_head_line(&mut reader, &mut line).await?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    Ok(Request { method, path, headers })
+}
+
+async fn read_head_line<R: AsyncBufRead + Unpin>(reader: &mut Take<R>, line: &mut String) -> io::Result<()> {
+    if reader.read_line(line).await? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Richiesta HTTP incompleta"));
+    }
+    if !line.ends_with('\n') && reader.limit() == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Intestazione HTTP troppo grande"));
+    }
+    Ok(())
+}
+
+async fn write_response<W: AsyncWrite + Unpin>(writer: &mut W, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body).await?;
+    writer.flush().await
+}
+
+// Invia i JPEG prodotti dal caster così come sono, uno per parte del multipart.
+async fn stream_mjpeg<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut rx: broadcast::Receiver<Arc<[u8]>>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\nCache-Control: no-cache\r\nPragma: no-cache\r\nConnection: close\r\n\r\n",
+        MJPEG_BOUNDARY
+    );
+    writer.write_all(head.as_bytes()).await?;
+
+    while !stop_signal.load(Ordering::SeqCst) && !terminate.load(Ordering::SeqCst) {
+        match rx.recv().await {
+            Ok(packet) => {
+                let jpeg = match protocol::parse_packet(&packet) {
+                    protocol::Packet::Frame(jpeg) => jpeg,
+                    protocol::Packet::KeepAlive | protocol::Packet::Resolution(..) | protocol::Packet::Annotations(_) => continue,
+                    protocol::Packet::EndOfStream => break,
+                };
+                let part_head = format!(
+                    "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                    MJPEG_BOUNDARY,
+                    jpeg.len()
+                );
+                writer.write_all(part_head.as_bytes()).await?;
+                writer.write_all(jpeg).await?;
+                writer.write_all(b"\r\n").await?;
+                writer.flush().await?;
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                eprintln!("Avviso: client MJPEG in ritardo, saltati {} frame", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+    writer.write_all(format!("--{}--\r\n", MJPEG_BOUNDARY).as_bytes()).await?;
+    writer.flush().await
+}
+
+fn websocket_accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    STANDARD.encode(hasher.finalize())
+}
+
+async fn stream_websocket<R: AsyncRead + Unpin + Send + 'static, W: AsyncWrite + Unpin>(
+    mut reader: R,
+    mut writer: W,
+    mut rx: broadcast::Receiver<Arc<[u8]>>,
+    stop_signal: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+) -> io::Result<()> {
+    // I frame di controllo del browser vengono letti in un task separato, così la lettura
+    // non viene interrotta a metà quando arriva un nuovo frame da inviare.
+    let (control_tx, mut control_rx) = mpsc::channel::<(u8, Vec<u8>)>(8);
+    tokio::spawn(async move {
+        while let Ok(frame) = read_ws_frame(&mut reader).await {
+            if control_tx.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while !stop_signal.load(Ordering::SeqCst) && !terminate.load(Ordering::SeqCst) {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(packet) => match protocol::parse_packet(&packet) {
+                    protocol::Packet::Frame(jpeg) => write_ws_frame(&mut writer, 0x2, jpeg).await?,
+                    protocol::Packet::KeepAlive | protocol::Packet::Resolution(..) | protocol::Packet::Annotations(_) => {}
+                    protocol::Packet::EndOfStream => {
+                        // Fine dello stream: chiude la connessione WebSocket
+                        write_ws_frame(&mut writer, 0x8, &[]).await?;
+                        break;
+                    }
+                },
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    eprintln!("Avviso: visualizzatore web in ritardo, saltati {} frame", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            incoming = control_rx.recv() => match incoming {
+                Some((0x8, _)) => {
+                    write_ws_frame(&mut writer, 0x8, &[]).await?;
+                    break;
+                }
+                Some((0x9, payload)) => write_ws_frame(&mut writer, 0xA, &payload).await?,
+                Some(_) => {}
+                None => break,
+            },
+        }
+    }
+    Ok(())
+}
+
+async fn write_ws_frame<W: AsyncWrite + Unpin>(writer: &mut W, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut header = Vec::with_capacity(10);
+    header.push(0x80 | opcode);
+    if payload.len() < 126 {
+        header.push(payload.len() as u8);
+    } else if payload.len() <= u16::MAX as usize {
+        header.push(126);
+        header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        header.push(127);
+        header.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    writer.write_all(&header).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
+
+// Legge un frame inviato dal browser; i frame client sono sempre mascherati.
+async fn read_ws_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header).await?;
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+    let len = match header[1] & 0x7F {
+        126 => {
+            let mut ext = [0u8; 2];
+            reader.read_exact(&mut ext).await?;
+            u16::from_be_bytes(ext) as u64
+        }
+        127 => {
+            let mut ext = [0u8; 8];
+            reader.read_exact(&mut ext).await?;
+            u64::from_be_bytes(ext)
+        }
+        n => n as u64,
+    };
+    if len > 65536 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame WebSocket del client troppo grande"));
+    }
+    let mut mask = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    if masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Ok((opcode, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    // Esempio della RFC 6455, sezione 1.3
+    #[test]
+    fn accept_key_matches_rfc() {
+        assert_eq!(websocket_accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[tokio::test]
+    async fn request_head_is_bounded() {
+        let request = b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\r\n";
+        let parsed = read_request(&mut BufReader::new(&request[..])).await.unwrap();
+        assert_eq!(parsed.method, "GET");
+        assert_eq!(parsed.path, "/ws");
+        assert_eq!(parsed.headers.get("upgrade").map(String::as_str), Some("websocket"));
+
+        // Una sola riga senza fine non deve essere letta oltre il limite
+        let mut endless = b"GET / HTTP/1.1\r\nX-Long: ".to_vec();
+        endless.resize(MAX_REQUEST_HEAD * 4, b'a');
+        let error = read_request(&mut BufReader::new(&endless[..])).await.err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    async fn serve_one(sender: Arc<broadcast::Sender<Arc<[u8]>>>) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, peer) = listener.accept().await.unwrap();
+            let stop = Arc::new(AtomicBool::new(false));
+            let _ = handle_connection(socket, peer, sender, Arc::clone(&stop), stop).await;
+        });
+        TcpStream::connect(addr).await.unwrap()
+    }
+
+    async fn read_head(client: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(client.read_u8().await.unwrap());
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    #[tokio::test]
+    async fn page_load_does_not_subscribe() {
+        let (sender, _) = broadcast::channel(4);
+        let sender = Arc::new(sender);
+        let mut client = serve_one(Arc::clone(&sender)).await;
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200 OK"));
+        assert_eq!(sender.receiver_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn websocket_handshake_and_frames() {
+        let (sender, _) = broadcast::channel(4);
+        let sender = Arc::new(sender);
+        let mut client = serve_one(Arc::clone(&sender)).await;
+        client.write_all(
+            b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        ).await.unwrap();
+        let head = read_head(&mut client).await;
+        assert!(head.starts_with("HTTP/1.1 101"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert_eq!(sender.receiver_count(), 1);
+
+        // Keep-alive e annotazioni non arrivano al browser, il JPEG sì come frame binario
+        let jpeg = [0xFF, 0xD8, 1, 2, 3, 0xFF, 0xD9];
+        let mut frame = Vec::new();
+        protocol::begin_frame(&mut frame);
+        frame.extend_from_slice(&jpeg);
+        protocol::finish_frame(&mut frame);
+        sender.send(protocol::keep_alive().into()).unwrap();
+        sender.send(protocol::annotations(&[1, 2, 3]).into()).unwrap();
+        sender.send(frame.into()).unwrap();
+        assert_eq!(read_ws_frame(&mut client).await.unwrap(), (0x2, jpeg.to_vec()));
+
+        // La fine dello stream chiude la connessione WebSocket
+        sender.send(protocol::end_of_stream().into()).unwrap();
+        assert_eq!(read_ws_frame(&mut client).await.unwrap(), (0x8, Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn stop_releases_the_port_without_new_clients() {
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().to_string();
+        let (sender, _) = broadcast::channel(4);
+        let stop = Arc::new(AtomicBool::new(false));
+        start_web_server(&addr, Arc::new(sender), Arc::clone(&stop), Arc::new(AtomicBool::new(false))).await.unwrap();
+        stop.store(true, Ordering::SeqCst);
+        sleep(STOP_CHECK_INTERVAL * 3).await;
+        // Un nuovo avvio sulla stessa porta deve riuscire
+        TcpListener::bind(&addr).await.unwrap();
+    }
+}